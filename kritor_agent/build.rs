@@ -0,0 +1,15 @@
+use std::{env, path::PathBuf};
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let descriptor_file =
+        PathBuf::from(env::var("OUT_DIR").unwrap()).join("agent_descriptor_set.bin");
+    tonic_build::configure()
+        .build_client(false)
+        .file_descriptor_set_path(descriptor_file)
+        .extern_path(".kritor", "::kritor")
+        .compile(
+            &["protos/agent.proto"],
+            &["protos", "../kritor/kritor/protos"],
+        )?;
+    Ok(())
+}
@@ -0,0 +1,164 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use std::sync::mpsc;
+use std::thread::JoinHandle;
+
+/// Appends lines to a file from a thread of its own, keeping file io off the
+/// async runtime. Lines sent at once are written in a batch.
+///
+/// Lines still pending are written when it is dropped.
+pub struct Appender {
+    sender: Option<mpsc::Sender<String>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+/// Which lines are kept when the file is rewritten, once it holds twice as
+/// many lines as kept.
+#[derive(Clone, Copy)]
+pub struct Retention {
+    /// how many of the latest lines are kept.
+    pub lines: usize,
+    /// lines are grouped by it, the latest line of every group being kept as well.
+    pub key: Option<fn(&str) -> &str>,
+}
+
+struct Writer {
+    path: PathBuf,
+    file: Option<BufWriter<File>>,
+    retention: Option<Retention>,
+    /// the latest lines, to rewrite the file with.
+    recent: VecDeque<String>,
+    /// the latest line of every group.
+    last: HashMap<String, String>,
+    /// lines in the file.
+    lines: usize,
+}
+
+impl Appender {
+    /// Append to the file, which keeps every line.
+    pub fn new(path: PathBuf) -> std::io::Result<Self> {
+        Self::spawn(Writer::new(path, None))
+    }
+    /// Append to the file holding the lines given, which is rewritten with
+    /// only the lines retained when it grows too large.
+    pub fn with_retention<'a>(
+        path: PathBuf,
+        retention: Retention,
+        lines: impl Iterator<Item = &'a str>,
+    ) -> std::io::Result<Self> {
+        let mut writer = Writer::new(path, Some(retention));
+        for line in lines {
+            writer.retain(format!("{}\n", line));
+            writer.lines += 1;
+        }
+        Self::spawn(writer)
+    }
+    fn spawn(mut writer: Writer) -> std::io::Result<Self> {
+        let name = format!(
+            "appender-{}",
+            writer
+                .path
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+        );
+        let (sender, receiver) = mpsc::channel::<String>();
+        let thread = std::thread::Builder::new().name(name).spawn(move || {
+            while let Ok(line) = receiver.recv() {
+                let lines = std::iter::once(line).chain(receiver.try_iter());
+                if let Err(e) = writer.append(lines) {
+                    log::error!("Failed to write to {}: {}", writer.path.display(), e);
+                    writer.file = None;
+                }
+            }
+        })?;
+        Ok(Self {
+            sender: Some(sender),
+            thread: Some(thread),
+        })
+    }
+    /// Append a line, which should end with a newline.
+    pub fn append(&self, line: String) {
+        if let Some(sender) = &self.sender {
+            let _ = sender.send(line);
+        }
+    }
+}
+
+impl Writer {
+    fn new(path: PathBuf, retention: Option<Retention>) -> Self {
+        Self {
+            path,
+            file: None,
+            retention,
+            recent: VecDeque::new(),
+            last: HashMap::new(),
+            lines: 0,
+        }
+    }
+    fn retain(&mut self, line: String) {
+        let Some(retention) = self.retention else {
+            return;
+        };
+        if let Some(key) = retention.key {
+            self.last.insert(key(&line).to_string(), line.clone());
+        }
+        if self.recent.len() >= retention.lines {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(line);
+    }
+    fn append(&mut self, lines: impl Iterator<Item = String>) -> std::io::Result<()> {
+        for line in lines {
+            if let Some(retention) = self.retention {
+                if self.lines >= (retention.lines + self.last.len()) * 2 {
+                    self.compact()?;
+                }
+            }
+            let file = match &mut self.file {
+                Some(file) => file,
+                None => self.file.insert(BufWriter::new(
+                    OpenOptions::new()
+                        .create(true)
+                        .append(true)
+                        .open(&self.path)?,
+                )),
+            };
+            file.write_all(line.as_bytes())?;
+            self.lines += 1;
+            self.retain(line);
+        }
+        match &mut self.file {
+            Some(file) => file.flush(),
+            None => Ok(()),
+        }
+    }
+    fn compact(&mut self) -> std::io::Result<()> {
+        if let Some(mut file) = self.file.take() {
+            file.flush()?;
+        }
+        let recent: HashSet<_> = self.recent.iter().collect();
+        let kept: Vec<_> = self
+            .last
+            .values()
+            .filter(|x| !recent.contains(x))
+            .chain(self.recent.iter())
+            .collect();
+        let tmp = self.path.with_extension("tmp");
+        std::fs::write(&tmp, kept.iter().map(|x| x.as_str()).collect::<String>())?;
+        std::fs::rename(&tmp, &self.path)?;
+        self.lines = kept.len();
+        Ok(())
+    }
+}
+
+impl Drop for Appender {
+    fn drop(&mut self) {
+        self.sender.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
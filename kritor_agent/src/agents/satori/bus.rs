@@ -0,0 +1,258 @@
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use futures_util::StreamExt;
+use kritor::event::EventStructure;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tonic::codegen::BoxStream;
+
+use super::filter::EventFilter;
+use super::replay::ReplayBuffer;
+
+/// What to do with a subscriber that falls behind by more than the bus capacity.
+#[derive(Debug, Default, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LagPolicy {
+    /// Log a warning and go on with the events still buffered.
+    #[default]
+    Warn,
+    /// End the subscription with a `RESOURCE_EXHAUSTED` status.
+    Disconnect,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct EventBusConfig {
+    /// How many events are buffered for subscribers.
+    #[serde(default = "EventBusConfig::default_capacity")]
+    pub capacity: usize,
+    #[serde(default)]
+    pub on_lag: LagPolicy,
+    /// How many recent events are kept for subscribers resuming after the
+    /// events they received.
+    #[serde(default = "EventBusConfig::default_replay")]
+    pub replay: usize,
+    /// File to persist the recent events in, so that they survive restarts.
+    pub replay_file: Option<PathBuf>,
+}
+
+impl EventBusConfig {
+    fn default_capacity() -> usize {
+        1024
+    }
+    fn default_replay() -> usize {
+        1024
+    }
+}
+
+impl Default for EventBusConfig {
+    fn default() -> Self {
+        Self {
+            capacity: Self::default_capacity(),
+            on_lag: LagPolicy::default(),
+            replay: Self::default_replay(),
+            replay_file: None,
+        }
+    }
+}
+
+/// A kritor event with where it comes from.
+#[derive(Debug, Clone)]
+pub struct BusEvent {
+    /// the bot account receiving the event, empty for events of the agent itself.
+    pub self_id: String,
+    pub event: EventStructure,
+}
+
+/// Delivers every kritor event to every subscriber, each with its own position
+/// in a bounded buffer.
+///
+/// Recent events are also kept in a replay buffer, so that subscribers may
+/// catch up with the events they missed before receiving live ones.
+pub struct EventBus {
+    /// events with their ids from the replay buffer.
+    sender: broadcast::Sender<(u64, BusEvent)>,
+    replay: Mutex<ReplayBuffer>,
+    on_lag: LagPolicy,
+    /// events missed by lagging subscribers, summed up over all of them.
+    dropped: AtomicU64,
+}
+
+impl EventBus {
+    pub fn new(config: &EventBusConfig) -> std::io::Result<Self> {
+        let replay = match &config.replay_file {
+            Some(path) => ReplayBuffer::open(config.replay, path)?,
+            None => ReplayBuffer::new(config.replay),
+        };
+        Ok(Self {
+            sender: broadcast::channel(config.capacity.max(1)).0,
+            replay: Mutex::new(replay),
+            on_lag: config.on_lag,
+            dropped: AtomicU64::new(0),
+        })
+    }
+    /// Send an event to all current subscribers.
+    pub fn publish(&self, event: BusEvent) {
+        // held while sending, so that subscribers see every event either replayed or live.
+        let mut replay = self.replay.lock().unwrap();
+        let id = replay.push(event.clone());
+        // having no subscriber is fine.
+        let _ = self.sender.send((id, event));
+    }
+    /// How many events lagging subscribers have missed so far.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+    /// Subscribe to the events passing the filter, starting with the buffered
+    /// ones after `resume_after` or from `resume_from` on if either is given.
+    pub fn subscribe(self: &Arc<Self>, filter: EventFilter) -> BoxStream<EventStructure> {
+        self.subscribe_with(filter, |_, event| event)
+    }
+    /// Like [`EventBus::subscribe`], turning the events with their ids into items.
+    pub fn subscribe_with<T: Send + 'static>(
+        self: &Arc<Self>,
+        filter: EventFilter,
+        item: fn(u64, EventStructure) -> T,
+    ) -> BoxStream<T> {
+        let bus = self.clone();
+        let (receiver, replayed) = {
+            let replay = self.replay.lock().unwrap();
+            let replayed: Vec<_> = match (filter.resume_after, filter.resume_from) {
+                (Some(id), _) => replay.after(id).collect(),
+                (None, Some(time)) => replay.since(time).collect(),
+                (None, None) => Vec::new(),
+            };
+            let replayed = replayed
+                .into_iter()
+                .filter(|(_, x)| filter.matches(x))
+                .map(|(id, x)| item(*id, x.event.clone()))
+                .collect::<Vec<_>>();
+            (self.sender.subscribe(), replayed)
+        };
+        let filter = Arc::new(filter);
+        let live = futures_util::stream::unfold(Some(receiver), move |receiver| {
+            let bus = bus.clone();
+            let filter = filter.clone();
+            async move {
+                let mut receiver = receiver?;
+                loop {
+                    match receiver.recv().await {
+                        Ok((id, event)) if filter.matches(&event) => {
+                            return Some((Ok(item(id, event.event)), Some(receiver)))
+                        }
+                        Ok(_) => {}
+                        Err(RecvError::Closed) => return None,
+                        Err(RecvError::Lagged(n)) => {
+                            let total = bus.dropped.fetch_add(n, Ordering::Relaxed) + n;
+                            match bus.on_lag {
+                                LagPolicy::Warn => log::warn!(
+                                    "A subscriber lagged behind and missed {} events, {} in total",
+                                    n,
+                                    total
+                                ),
+                                LagPolicy::Disconnect => {
+                                    log::warn!(
+                                            "Disconnecting a subscriber that missed {} events, {} in total",
+                                            n,
+                                            total
+                                        );
+                                    let status = tonic::Status::resource_exhausted(format!(
+                                        "subscriber lagged behind by {} events",
+                                        n
+                                    ));
+                                    return Some((Err(status), None));
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        });
+        Box::pin(futures_util::stream::iter(replayed.into_iter().map(Ok)).chain(live))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::replay::event_time;
+    use super::*;
+
+    #[tokio::test]
+    async fn test_lag() {
+        let bus = Arc::new(
+            EventBus::new(&EventBusConfig {
+                capacity: 2,
+                on_lag: LagPolicy::Warn,
+                ..Default::default()
+            })
+            .unwrap(),
+        );
+        let mut stream = bus.subscribe(EventFilter::default());
+        for _ in 0..5 {
+            bus.publish(BusEvent {
+                self_id: String::new(),
+                event: EventStructure::default(),
+            });
+        }
+        // the oldest events are missed, the buffered ones are still delivered.
+        assert!(stream.next().await.unwrap().is_ok());
+        assert!(stream.next().await.unwrap().is_ok());
+        assert_eq!(bus.dropped(), 3);
+
+        let bus = Arc::new(
+            EventBus::new(&EventBusConfig {
+                capacity: 2,
+                on_lag: LagPolicy::Disconnect,
+                ..Default::default()
+            })
+            .unwrap(),
+        );
+        let mut stream = bus.subscribe(EventFilter::default());
+        for _ in 0..5 {
+            bus.publish(BusEvent {
+                self_id: String::new(),
+                event: EventStructure::default(),
+            });
+        }
+        assert!(stream.next().await.unwrap().is_err());
+        assert!(stream.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_resume() {
+        let bus = Arc::new(EventBus::new(&EventBusConfig::default()).unwrap());
+        let notice = |time| BusEvent {
+            self_id: String::new(),
+            event: EventStructure {
+                r#type: kritor::event::EventType::Notice.into(),
+                event: Some(kritor::event::event_structure::Event::Notice(
+                    kritor::event::NoticeEvent {
+                        time,
+                        ..Default::default()
+                    },
+                )),
+            },
+        };
+        bus.publish(notice(1));
+        bus.publish(notice(2));
+        let mut stream = bus.subscribe(EventFilter {
+            resume_from: Some(2),
+            ..Default::default()
+        });
+        bus.publish(notice(3));
+        for time in [2, 3] {
+            let event = stream.next().await.unwrap().unwrap();
+            assert_eq!(event_time(&event), time);
+        }
+        // ids tell events of the same second apart.
+        bus.publish(notice(3));
+        let mut stream = bus.subscribe_with(
+            EventFilter {
+                resume_after: Some(3),
+                ..Default::default()
+            },
+            |id, event| (id, event_time(&event)),
+        );
+        assert_eq!(stream.next().await.unwrap().unwrap(), (4, 3));
+    }
+}
@@ -39,6 +39,7 @@ impl From<reqwest::StatusCode> for Error {
     }
 }
 
+#[derive(Clone)]
 pub struct SatoriClient {
     /// a reqwest client to be used to send http requests to a satori server.
     pub client: reqwest::Client,
@@ -49,9 +50,21 @@ pub struct SatoriClient {
     /// This is to make proper relative url for rpc calls.
     pub base_url: Url,
     pub token: Option<String>,
+    /// sent as `X-Platform`, selecting the login to act as together with `self_id`.
+    pub platform: Option<String>,
+    /// sent as `X-Self-ID`.
+    pub self_id: Option<String>,
 }
 
 impl SatoriClient {
+    /// A client acting as the login.
+    pub fn with_login(&self, login: &schema::Login) -> Self {
+        Self {
+            platform: login.platform.clone().or(self.platform.clone()),
+            self_id: login.self_id.clone().or(self.self_id.clone()),
+            ..self.clone()
+        }
+    }
     #[inline]
     async fn rpc<Resp: DeserializeOwned>(&self, endpoint: &str) -> Result<Resp, Error> {
         self.make_rpc_req(endpoint, None::<()>).await
@@ -74,6 +87,12 @@ impl SatoriClient {
         if let Some(token) = &self.token {
             req = req.header("Authorization", format!("Bearer {}", token));
         }
+        if let Some(platform) = &self.platform {
+            req = req.header("X-Platform", platform);
+        }
+        if let Some(self_id) = &self.self_id {
+            req = req.header("X-Self-ID", self_id);
+        }
         if let Some(body) = body {
             req = req.json(&body);
         }
@@ -108,4 +127,113 @@ impl SatoriClient {
         )
         .await
     }
+
+    pub async fn user_channel_create(
+        &self,
+        user_id: String,
+        guild_id: Option<String>,
+    ) -> Result<schema::Channel, Error> {
+        #[derive(Serialize)]
+        struct Req {
+            user_id: String,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            guild_id: Option<String>,
+        }
+        self.rpc_with("user.channel.create", Req { user_id, guild_id })
+            .await
+    }
+
+    pub async fn guild_list(
+        &self,
+        next: Option<String>,
+    ) -> Result<schema::List<schema::Guild>, Error> {
+        #[derive(Serialize)]
+        struct Req {
+            next: Option<String>,
+        }
+        self.rpc_with("guild.list", Req { next }).await
+    }
+
+    pub async fn guild_get(&self, guild_id: String) -> Result<schema::Guild, Error> {
+        #[derive(Serialize)]
+        struct Req {
+            guild_id: String,
+        }
+        self.rpc_with("guild.get", Req { guild_id }).await
+    }
+
+    pub async fn channel_list(
+        &self,
+        guild_id: String,
+        next: Option<String>,
+    ) -> Result<schema::List<schema::Channel>, Error> {
+        #[derive(Serialize)]
+        struct Req {
+            guild_id: String,
+            next: Option<String>,
+        }
+        self.rpc_with("channel.list", Req { guild_id, next }).await
+    }
+
+    pub async fn guild_member_list(
+        &self,
+        guild_id: String,
+        next: Option<String>,
+    ) -> Result<schema::List<schema::GuildMember>, Error> {
+        #[derive(Serialize)]
+        struct Req {
+            guild_id: String,
+            next: Option<String>,
+        }
+        self.rpc_with("guild.member.list", Req { guild_id, next })
+            .await
+    }
+
+    pub async fn guild_member_get(
+        &self,
+        guild_id: String,
+        user_id: String,
+    ) -> Result<schema::GuildMember, Error> {
+        #[derive(Serialize)]
+        struct Req {
+            guild_id: String,
+            user_id: String,
+        }
+        self.rpc_with("guild.member.get", Req { guild_id, user_id })
+            .await
+    }
+
+    pub async fn guild_role_list(
+        &self,
+        guild_id: String,
+        next: Option<String>,
+    ) -> Result<schema::List<schema::GuildRole>, Error> {
+        #[derive(Serialize)]
+        struct Req {
+            guild_id: String,
+            next: Option<String>,
+        }
+        self.rpc_with("guild.role.list", Req { guild_id, next })
+            .await
+    }
+
+    pub async fn message_get(
+        &self,
+        channel_id: String,
+        message_id: String,
+    ) -> Result<schema::Message, Error> {
+        #[derive(Serialize)]
+        struct Req {
+            channel_id: String,
+            message_id: String,
+        }
+        self.rpc_with(
+            "message.get",
+            Req {
+                channel_id,
+                message_id,
+            },
+        )
+        .await
+    }
 }
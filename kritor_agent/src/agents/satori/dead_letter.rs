@@ -0,0 +1,119 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use super::link;
+
+/// A signal of the satori server that could not be turned into a kritor event.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct DeadLetter {
+    /// increasing from 1, so that readers can continue where they left off.
+    pub id: u64,
+    /// unix time in seconds when it was received.
+    pub time: u64,
+    pub reason: String,
+    /// whether it is a valid event kritor has no counterpart of, rather than
+    /// one that failed to decode or convert.
+    pub unsupported: bool,
+    /// the signal or event as received.
+    pub raw: String,
+}
+
+/// The latest dead letters, the oldest ones dropped beyond the capacity.
+pub struct DeadLetters {
+    letters: Mutex<VecDeque<DeadLetter>>,
+    capacity: usize,
+    failed: AtomicU64,
+    unsupported: AtomicU64,
+}
+
+impl Default for DeadLetters {
+    fn default() -> Self {
+        Self::new(256)
+    }
+}
+
+impl DeadLetters {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            letters: Mutex::new(VecDeque::new()),
+            capacity,
+            failed: AtomicU64::new(0),
+            unsupported: AtomicU64::new(0),
+        }
+    }
+    /// Record a signal that failed to be handled.
+    pub fn record(&self, raw: String, reason: String) {
+        log::warn!("Failed to handle satori signal: {}", reason);
+        log::debug!("The failed signal: {}", raw);
+        self.failed.fetch_add(1, Ordering::Relaxed);
+        self.push(raw, reason, false);
+    }
+    /// Record a valid event kritor has no counterpart of, which is common
+    /// enough not to be warned about.
+    pub fn record_unsupported(&self, raw: String, reason: String) {
+        log::debug!("Skipping satori event: {}", reason);
+        self.unsupported.fetch_add(1, Ordering::Relaxed);
+        self.push(raw, reason, true);
+    }
+    /// How many signals failed to be handled since the start.
+    pub fn failed(&self) -> u64 {
+        self.failed.load(Ordering::Relaxed)
+    }
+    /// How many events were unsupported since the start.
+    pub fn unsupported(&self) -> u64 {
+        self.unsupported.load(Ordering::Relaxed)
+    }
+    fn push(&self, raw: String, reason: String, unsupported: bool) {
+        let mut letters = self.letters.lock().unwrap();
+        let id = letters.back().map_or(1, |x| x.id + 1);
+        if letters.len() >= self.capacity {
+            letters.pop_front();
+        }
+        if self.capacity > 0 {
+            letters.push_back(DeadLetter {
+                id,
+                time: link::now(),
+                reason,
+                unsupported,
+                raw,
+            });
+        }
+    }
+    /// The dead letters from the id on, oldest first.
+    pub fn since(&self, start: u64) -> Vec<DeadLetter> {
+        self.letters
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|x| x.id >= start)
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dead_letters() {
+        let letters = DeadLetters::new(2);
+        for i in 0..2 {
+            letters.record(i.to_string(), "invalid".into());
+        }
+        letters.record_unsupported(2.to_string(), "unsupported".into());
+        let ids = |start| {
+            letters
+                .since(start)
+                .into_iter()
+                .map(|x| x.id)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(ids(0), vec![2, 3]);
+        assert_eq!(ids(3), vec![3]);
+        assert_eq!(letters.since(0)[0].raw, "1");
+        assert!(letters.since(3)[0].unsupported);
+        assert_eq!((letters.failed(), letters.unsupported()), (2, 1));
+    }
+}
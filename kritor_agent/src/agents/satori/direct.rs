@@ -0,0 +1,92 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+
+/// bot account, user and guild.
+type Key = (String, String, Option<String>);
+
+/// Direct channels of users, as satori ids of direct channels are not derivable
+/// from the user ids.
+///
+/// A channel is keyed by the bot account, the user and, for temporary sessions,
+/// the guild it comes from. They are learned from `user.channel.create`
+/// and from incoming direct messages. Only the latest `capacity` users are kept,
+/// the others are looked up again when messaged.
+pub struct DirectChannels {
+    state: Mutex<State>,
+    capacity: usize,
+}
+
+#[derive(Default)]
+struct State {
+    channels: HashMap<Key, String>,
+    /// keys from old to new, to forget the oldest ones.
+    order: VecDeque<Key>,
+}
+
+impl Default for DirectChannels {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_CAPACITY)
+    }
+}
+
+impl DirectChannels {
+    pub const DEFAULT_CAPACITY: usize = 10_000;
+
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            state: Default::default(),
+            capacity,
+        }
+    }
+    pub fn get(&self, self_id: &str, user_id: &str, guild_id: Option<&str>) -> Option<String> {
+        self.state
+            .lock()
+            .unwrap()
+            .channels
+            .get(&(
+                self_id.to_string(),
+                user_id.to_string(),
+                guild_id.map(ToString::to_string),
+            ))
+            .cloned()
+    }
+    pub fn insert(
+        &self,
+        self_id: String,
+        user_id: String,
+        guild_id: Option<String>,
+        channel_id: String,
+    ) {
+        let mut state = self.state.lock().unwrap();
+        let key = (self_id, user_id, guild_id);
+        if state.channels.insert(key.clone(), channel_id).is_some() {
+            return;
+        }
+        state.order.push_back(key);
+        while state.order.len() > self.capacity {
+            let Some(key) = state.order.pop_front() else {
+                break;
+            };
+            state.channels.remove(&key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_direct_channels_capacity() {
+        let channels = DirectChannels::new(2);
+        channels.insert("bot".into(), "a".into(), None, "1".into());
+        channels.insert("bot".into(), "b".into(), None, "2".into());
+        // a channel learned again does not take another place.
+        channels.insert("bot".into(), "a".into(), None, "3".into());
+        channels.insert("bot".into(), "c".into(), Some("g".into()), "4".into());
+        assert_eq!(channels.get("bot", "a", None), None);
+        assert_eq!(channels.get("bot", "b", None).as_deref(), Some("2"));
+        assert_eq!(channels.get("bot", "c", Some("g")).as_deref(), Some("4"));
+        assert_eq!(channels.get("bot", "c", None), None);
+    }
+}
@@ -0,0 +1,164 @@
+use std::collections::HashSet;
+
+use kritor::common::Scene;
+use kritor::event::{event_structure::Event, EventType, RequestPushEvent};
+use tonic::metadata::MetadataMap;
+
+use super::{bus::BusEvent, IdentityRegistry};
+
+/// Which events a subscriber wants.
+///
+/// Besides the kritor event type, subscribers may narrow down their events with
+/// comma separated lists in the request metadata:
+///
+/// - `x-filter-groups`: groups, guilds or channels, by id or uin, matching the peer
+///   or sub_peer of the contact.
+/// - `x-filter-friends`: users of private messages, by uid or uin.
+/// - `x-filter-self-ids`: bot accounts the events are received by,
+///   defaulting to the account selected by `x-self-id`.
+///
+/// Group and friend filters only apply to message events. If both are given,
+/// a message passes when it matches either of them.
+///
+/// `x-resume-from`, a unix timestamp in seconds, asks for the buffered events
+/// from that time on before the live ones. As times are in whole seconds and
+/// not in order, `resume_after` of `AgentService.SubscribeEvents`, an event id,
+/// is the reliable way to resume.
+#[derive(Debug, Default)]
+pub struct EventFilter {
+    pub r#type: Option<EventType>,
+    pub groups: Option<HashSet<String>>,
+    pub friends: Option<HashSet<String>>,
+    pub self_ids: Option<HashSet<String>>,
+    /// id of the last event received.
+    pub resume_after: Option<u64>,
+    pub resume_from: Option<u64>,
+}
+
+impl EventFilter {
+    pub fn from_request(
+        request: &RequestPushEvent,
+        metadata: &MetadataMap,
+        identities: &IdentityRegistry,
+        guild_identities: &IdentityRegistry,
+    ) -> Self {
+        let list = |key: &str| {
+            metadata.get(key).and_then(|x| x.to_str().ok()).map(|x| {
+                x.split(',')
+                    .map(str::trim)
+                    .filter(|x| !x.is_empty())
+                    .map(str::to_string)
+                    .collect::<Vec<_>>()
+            })
+        };
+        // contacts have uins as peers, the ids may be either.
+        let peers = |key: &str, registry: &IdentityRegistry| {
+            list(key).map(|x| {
+                x.into_iter()
+                    .map(|x| registry.peer(&registry.resolve_peer(x)))
+                    .collect::<HashSet<_>>()
+            })
+        };
+        // self ids of events are platform ids.
+        let self_ids = |key: &str| {
+            list(key).map(|x| {
+                x.into_iter()
+                    .map(|x| identities.resolve_peer(x))
+                    .collect::<HashSet<_>>()
+            })
+        };
+        Self {
+            r#type: EventType::try_from(request.r#type).ok(),
+            groups: peers("x-filter-groups", guild_identities),
+            friends: peers("x-filter-friends", identities),
+            self_ids: self_ids("x-filter-self-ids").or(self_ids("x-self-id")),
+            resume_after: None,
+            resume_from: metadata
+                .get("x-resume-from")
+                .and_then(|x| x.to_str().ok())
+                .and_then(|x| x.trim().parse().ok()),
+        }
+    }
+    pub fn matches(&self, event: &BusEvent) -> bool {
+        if self
+            .r#type
+            .is_some_and(|x| i32::from(x) != event.event.r#type)
+        {
+            return false;
+        }
+        // events of the agent itself concern every account.
+        if !event.self_id.is_empty()
+            && self
+                .self_ids
+                .as_ref()
+                .is_some_and(|x| !x.contains(&event.self_id))
+        {
+            return false;
+        }
+        if self.groups.is_none() && self.friends.is_none() {
+            return true;
+        }
+        let Some(Event::Message(message)) = &event.event.event else {
+            return true;
+        };
+        let Some(contact) = &message.contact else {
+            return false;
+        };
+        match Scene::try_from(contact.scene) {
+            Ok(Scene::Group | Scene::Guild) => self.groups.as_ref().is_some_and(|x| {
+                x.contains(&contact.peer)
+                    || contact.sub_peer.as_ref().is_some_and(|y| x.contains(y))
+            }),
+            Ok(Scene::Friend | Scene::Stranger | Scene::StrangerFromGroup) => self
+                .friends
+                .as_ref()
+                .is_some_and(|x| x.contains(&contact.peer)),
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kritor::common::{Contact, PushMessageBody};
+    use kritor::event::EventStructure;
+
+    #[test]
+    fn test_filter() {
+        let mut metadata = MetadataMap::new();
+        metadata.insert("x-filter-groups", "1, 2".parse().unwrap());
+        let filter = EventFilter::from_request(
+            &RequestPushEvent {
+                r#type: EventType::Message.into(),
+            },
+            &metadata,
+            &Default::default(),
+            &Default::default(),
+        );
+        let message = |scene: Scene, peer: &str| BusEvent {
+            self_id: "10000".into(),
+            event: EventStructure {
+                r#type: EventType::Message.into(),
+                event: Some(Event::Message(PushMessageBody {
+                    contact: Some(Contact {
+                        scene: scene.into(),
+                        peer: peer.into(),
+                        sub_peer: None,
+                    }),
+                    ..Default::default()
+                })),
+            },
+        };
+        assert!(filter.matches(&message(Scene::Group, "2")));
+        assert!(!filter.matches(&message(Scene::Group, "3")));
+        assert!(!filter.matches(&message(Scene::Friend, "2")));
+        assert!(!filter.matches(&BusEvent {
+            self_id: "10000".into(),
+            event: EventStructure {
+                r#type: EventType::Notice.into(),
+                event: None,
+            },
+        }));
+    }
+}
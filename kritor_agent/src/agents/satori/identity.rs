@@ -0,0 +1,190 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Mutex;
+
+use super::appender::Appender;
+
+/// Uins generated for other ids are from here up to `MAX_UIN`, beyond the ids
+/// of platforms such as QQ.
+const GENERATED_UIN_BASE: u64 = 1 << 52;
+/// The largest uin, which is also exact as a double, for kritor clients in
+/// languages such as JavaScript.
+const MAX_UIN: u64 = (1 << 53) - 1;
+
+/// Stable numeric uins for satori platform ids.
+///
+/// kritor identifies users by a numeric uin, while satori ids are arbitrary strings.
+/// Numeric ids below `GENERATED_UIN_BASE` are used as the uin directly, other
+/// ids are assigned one.
+/// The platform id itself is what kritor sees as the uid.
+///
+/// The agent keeps one registry for users and another for guilds, channels
+/// and roles, whose uins kritor does not mix with those of users.
+///
+/// Ids are not scoped by platform: with logins of several platforms, the same
+/// id on two platforms gets the same uin and is taken for the same user.
+///
+/// Assignments are appended to a file as `{uin}\t{uid}` lines if a path is given,
+/// so that they survive restarts. Backslashes, tabs and line breaks in the uid
+/// are escaped as `\\`, `\t`, `\n` and `\r`.
+#[derive(Default)]
+pub struct IdentityRegistry {
+    identities: Mutex<Identities>,
+    appender: Option<Appender>,
+}
+
+#[derive(Default)]
+struct Identities {
+    uins: HashMap<String, u64>,
+    uids: HashMap<u64, String>,
+    next_generated: u64,
+}
+
+impl Identities {
+    fn insert(&mut self, uin: u64, uid: String) {
+        if (GENERATED_UIN_BASE..=MAX_UIN).contains(&uin) {
+            self.next_generated = self.next_generated.max(uin - GENERATED_UIN_BASE + 1);
+        }
+        self.uids.insert(uin, uid.clone());
+        self.uins.insert(uid, uin);
+    }
+}
+
+impl IdentityRegistry {
+    /// Load the registry from the file, which is created on first assignment if missing.
+    pub fn open(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let mut identities = Identities::default();
+        match std::fs::read_to_string(&path) {
+            Ok(content) => {
+                for line in content.lines() {
+                    match line
+                        .split_once('\t')
+                        .and_then(|(uin, uid)| Some((uin.parse().ok()?, uid)))
+                    {
+                        Some((uin, uid)) => identities.insert(uin, unescape(uid)),
+                        _ => log::warn!("Ignoring malformed identity line: {:?}", line),
+                    }
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        Ok(Self {
+            identities: Mutex::new(identities),
+            appender: Some(Appender::new(path)?),
+        })
+    }
+    /// The uin of a platform id, assigning one if it has none yet.
+    ///
+    /// An empty id is no user, whose uin is 0.
+    pub fn uin(&self, uid: &str) -> u64 {
+        if uid.is_empty() {
+            return 0;
+        }
+        let mut identities = self.identities.lock().unwrap();
+        if let Some(uin) = identities.uins.get(uid) {
+            return *uin;
+        }
+        let uin = match uid.parse::<u64>() {
+            Ok(uin) if uin < GENERATED_UIN_BASE && !identities.uids.contains_key(&uin) => uin,
+            _ => {
+                let mut uin = GENERATED_UIN_BASE + identities.next_generated;
+                while identities.uids.contains_key(&uin) {
+                    uin += 1;
+                }
+                uin
+            }
+        };
+        identities.insert(uin, uid.to_string());
+        if let Some(appender) = &self.appender {
+            appender.append(format!("{}\t{}\n", uin, escape(uid)));
+        }
+        uin
+    }
+    /// The platform id a uin has been assigned to.
+    pub fn uid(&self, uin: u64) -> Option<String> {
+        self.identities.lock().unwrap().uids.get(&uin).cloned()
+    }
+    /// The platform id of a kritor user given by uid or uin, preferring the uid.
+    pub fn platform_id(&self, uid: Option<String>, uin: u64) -> String {
+        uid.or_else(|| self.uid(uin))
+            .unwrap_or_else(|| uin.to_string())
+    }
+    /// The kritor peer of a platform id, which is its uin.
+    pub fn peer(&self, id: &str) -> String {
+        self.uin(id).to_string()
+    }
+    /// Turn a kritor peer, which may be a uin, back into the platform id.
+    pub fn resolve_peer(&self, peer: String) -> String {
+        match peer.parse() {
+            Ok(uin) => self.uid(uin).unwrap_or(peer),
+            Err(_) => peer,
+        }
+    }
+}
+
+fn escape(uid: &str) -> String {
+    let mut escaped = String::with_capacity(uid.len());
+    for c in uid.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\t' => escaped.push_str("\\t"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn unescape(uid: &str) -> String {
+    let mut unescaped = String::with_capacity(uid.len());
+    let mut chars = uid.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('t') => unescaped.push('\t'),
+            Some('n') => unescaped.push('\n'),
+            Some('r') => unescaped.push('\r'),
+            // a lone backslash is kept as it is.
+            Some(c) if c != '\\' => unescaped.extend(['\\', c]),
+            _ => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_identity_persistence() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("identities.tsv");
+        let registry = IdentityRegistry::open(&path).unwrap();
+        assert_eq!(registry.uin("10086"), 10086);
+        assert_eq!(registry.uin(""), 0);
+        assert_eq!(registry.uid(0), None);
+        let generated = registry.uin("E6A3F0C2B1D4");
+        assert!((GENERATED_UIN_BASE..=MAX_UIN).contains(&generated));
+        assert_eq!(registry.uin("E6A3F0C2B1D4"), generated);
+        assert_eq!(registry.resolve_peer(generated.to_string()), "E6A3F0C2B1D4");
+        // numeric ids too large for doubles are assigned one as well.
+        assert!(registry.uin("1234567890123456789") <= MAX_UIN);
+        // separators in ids do not break the file.
+        let separated = registry.uin("a\tb\nc\\t");
+        // dropping the registry finishes writing the file.
+        drop(registry);
+
+        let registry = IdentityRegistry::open(&path).unwrap();
+        assert_eq!(registry.uid(generated).as_deref(), Some("E6A3F0C2B1D4"));
+        assert_ne!(registry.uin("another"), generated);
+        assert_eq!(registry.uid(separated).as_deref(), Some("a\tb\nc\\t"));
+        assert_eq!(registry.uin("a\tb\nc\\t"), separated);
+    }
+}
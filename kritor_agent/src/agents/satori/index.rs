@@ -0,0 +1,248 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::path::Path;
+use std::sync::Mutex;
+
+use super::appender::{Appender, Retention};
+
+/// Sequence numbers of messages, which satori does not have.
+///
+/// Every channel has its own sequence, starting at 1 and assigned in the order
+/// the agent sees the messages, whether received or sent. Only the latest
+/// `capacity` assignments are kept, besides the last one of every channel so
+/// that its sequence goes on, which is forgotten once the channel has a newer one.
+///
+/// Assignments are appended to a file as `{seq}\t{channel_id}\t{message_id}` lines
+/// if a path is given, so that they survive restarts. The file is rewritten
+/// with only the kept assignments once it holds twice as many.
+pub struct MessageIndex {
+    state: Mutex<State>,
+    capacity: usize,
+    appender: Option<Appender>,
+}
+
+#[derive(Default)]
+struct State {
+    channels: HashMap<String, Channel>,
+    /// assignments from old to new, to forget the oldest ones.
+    order: VecDeque<(String, u64)>,
+}
+
+#[derive(Default)]
+struct Channel {
+    seqs: HashMap<String, u64>,
+    ids: BTreeMap<u64, String>,
+    /// the last seq, kept past its turn to be forgotten until a newer one comes.
+    kept: Option<u64>,
+}
+
+impl Channel {
+    fn insert(&mut self, seq: u64, message_id: String) {
+        if let Some(kept) = self.kept.filter(|x| *x < seq) {
+            self.remove(kept);
+            self.kept = None;
+        }
+        self.ids.insert(seq, message_id.clone());
+        self.seqs.insert(message_id, seq);
+    }
+    fn remove(&mut self, seq: u64) {
+        if let Some(message_id) = self.ids.remove(&seq) {
+            self.seqs.remove(&message_id);
+        }
+    }
+    fn last_seq(&self) -> u64 {
+        self.ids.keys().next_back().copied().unwrap_or_default()
+    }
+}
+
+impl State {
+    fn insert(&mut self, capacity: usize, channel_id: &str, seq: u64, message_id: String) {
+        self.channels
+            .entry(channel_id.to_string())
+            .or_default()
+            .insert(seq, message_id);
+        self.order.push_back((channel_id.to_string(), seq));
+        while self.order.len() > capacity {
+            let Some((channel_id, seq)) = self.order.pop_front() else {
+                break;
+            };
+            let Some(channel) = self.channels.get_mut(&channel_id) else {
+                continue;
+            };
+            if seq == channel.last_seq() {
+                channel.kept = Some(seq);
+                continue;
+            }
+            channel.remove(seq);
+        }
+    }
+}
+
+/// The channel of a line in the file.
+fn line_channel(line: &str) -> &str {
+    line.split('\t').nth(1).unwrap_or_default()
+}
+
+impl Default for MessageIndex {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_CAPACITY)
+    }
+}
+
+impl MessageIndex {
+    pub const DEFAULT_CAPACITY: usize = 100_000;
+
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            state: Default::default(),
+            capacity,
+            appender: None,
+        }
+    }
+    /// Load the index from the file, which is created on first assignment if missing.
+    pub fn open(path: impl AsRef<Path>, capacity: usize) -> std::io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let mut state = State::default();
+        let content = match std::fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e),
+        };
+        for line in content.lines() {
+            let mut fields = line.splitn(3, '\t');
+            match (
+                fields.next().and_then(|x| x.parse().ok()),
+                fields.next(),
+                fields.next(),
+            ) {
+                (Some(seq), Some(channel_id), Some(message_id)) => {
+                    state.insert(capacity, channel_id, seq, message_id.to_string())
+                }
+                _ => log::warn!("Ignoring malformed message index line: {:?}", line),
+            }
+        }
+        let retention = Retention {
+            lines: capacity,
+            key: Some(line_channel),
+        };
+        Ok(Self {
+            state: Mutex::new(state),
+            capacity,
+            appender: Some(Appender::with_retention(path, retention, content.lines())?),
+        })
+    }
+    /// The seq of a message, assigning the next one of the channel if it has none yet.
+    pub fn seq(&self, channel_id: &str, message_id: &str) -> u64 {
+        let mut state = self.state.lock().unwrap();
+        let seq = match state.channels.get(channel_id) {
+            Some(channel) => match channel.seqs.get(message_id) {
+                Some(seq) => return *seq,
+                None => channel.last_seq() + 1,
+            },
+            None => 1,
+        };
+        state.insert(self.capacity, channel_id, seq, message_id.to_string());
+        if let Some(appender) = &self.appender {
+            appender.append(format!("{}\t{}\t{}\n", seq, channel_id, message_id));
+        }
+        seq
+    }
+    pub fn message_id(&self, channel_id: &str, seq: u64) -> Option<String> {
+        self.state
+            .lock()
+            .unwrap()
+            .channels
+            .get(channel_id)?
+            .ids
+            .get(&seq)
+            .cloned()
+    }
+    /// Up to `count` messages of the channel with seqs not greater than `start`,
+    /// or the latest ones without `start`, as `(seq, message_id)` from old to new.
+    pub fn history(
+        &self,
+        channel_id: &str,
+        start: Option<u64>,
+        count: usize,
+    ) -> Vec<(u64, String)> {
+        let state = self.state.lock().unwrap();
+        let Some(channel) = state.channels.get(channel_id) else {
+            return Vec::new();
+        };
+        let mut result: Vec<_> = channel
+            .ids
+            .range(..=start.unwrap_or(u64::MAX))
+            .rev()
+            .take(count)
+            .map(|(seq, id)| (*seq, id.clone()))
+            .collect();
+        result.reverse();
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_message_index() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("messages.tsv");
+        let index = MessageIndex::open(&path, 10).unwrap();
+        assert_eq!(index.seq("group", "a"), 1);
+        assert_eq!(index.seq("group", "b"), 2);
+        assert_eq!(index.seq("group", "a"), 1);
+        assert_eq!(index.seq("private:10086", "c"), 1);
+        // dropping the index finishes writing the file.
+        drop(index);
+
+        let index = MessageIndex::open(&path, 10).unwrap();
+        assert_eq!(index.message_id("group", 2).as_deref(), Some("b"));
+        assert_eq!(index.seq("group", "d"), 3);
+        let history: Vec<_> = index
+            .history("group", Some(2), 10)
+            .into_iter()
+            .map(|x| x.0)
+            .collect();
+        assert_eq!(history, [1, 2]);
+        assert_eq!(index.history("group", None, 1)[0].1, "d");
+    }
+
+    #[test]
+    fn test_message_index_retention() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("messages.tsv");
+        let index = MessageIndex::open(&path, 2).unwrap();
+        for i in 1..=10 {
+            assert_eq!(index.seq("group", &format!("m{}", i)), i);
+        }
+        assert_eq!(index.seq("private:10086", "c"), 1);
+        // the oldest are forgotten, the last of every channel is kept.
+        assert_eq!(index.message_id("group", 8), None);
+        assert_eq!(index.message_id("group", 10).as_deref(), Some("m10"));
+        assert_eq!(index.history("group", None, 10).len(), 1);
+        drop(index);
+        // rewritten once it held twice as many lines as kept.
+        assert!(std::fs::read_to_string(&path).unwrap().lines().count() < 8);
+
+        let index = MessageIndex::open(&path, 2).unwrap();
+        assert_eq!(index.message_id("group", 10).as_deref(), Some("m10"));
+        assert_eq!(index.seq("group", "m11"), 11);
+        assert_eq!(index.seq("private:10086", "d"), 2);
+    }
+
+    #[test]
+    fn test_message_index_interleaved() {
+        let index = MessageIndex::new(2);
+        for i in 1..=100 {
+            index.seq("quiet", &format!("q{}", i));
+            index.seq("busy", &format!("b{}", i));
+            index.seq("busy", &format!("c{}", i));
+        }
+        // the last seqs kept past their turn are forgotten once newer ones come.
+        assert_eq!(index.history("quiet", None, 1000).len(), 1);
+        assert!(index.history("busy", None, 1000).len() <= 2);
+        assert_eq!(index.message_id("quiet", 100).as_deref(), Some("q100"));
+        assert_eq!(index.message_id("quiet", 99), None);
+    }
+}
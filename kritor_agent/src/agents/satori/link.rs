@@ -0,0 +1,100 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use kritor::event::{
+    event_structure, notice_event::NoticeType, EventStructure, EventType, NoticeEvent,
+};
+
+/// State of the event websocket to the satori server.
+#[derive(Default)]
+pub struct LinkState {
+    connected: AtomicBool,
+    /// unix time in seconds of the last connection, 0 if never connected.
+    last_connected: AtomicU64,
+    connections: AtomicU64,
+}
+
+pub(crate) fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_secs())
+        .unwrap_or_default()
+}
+
+impl LinkState {
+    /// Mark the websocket connected, returning the number of the connection,
+    /// counting from 1.
+    pub fn connect(&self) -> u64 {
+        self.connected.store(true, Ordering::Relaxed);
+        self.last_connected.store(now(), Ordering::Relaxed);
+        self.connections.fetch_add(1, Ordering::Relaxed) + 1
+    }
+    pub fn disconnect(&self) {
+        self.connected.store(false, Ordering::Relaxed);
+    }
+    pub fn is_connected(&self) -> bool {
+        self.connected.load(Ordering::Relaxed)
+    }
+    pub fn last_connected(&self) -> Option<u64> {
+        Some(self.last_connected.load(Ordering::Relaxed)).filter(|x| *x != 0)
+    }
+    /// How many times the websocket connected again after the first time.
+    pub fn reconnects(&self) -> u64 {
+        self.connections.load(Ordering::Relaxed).saturating_sub(1)
+    }
+}
+
+/// A core event, telling that the state of the link or logins changed.
+///
+/// kritor core events carry no data, so the `notice_id` is only the id of the
+/// event: the id of satori login events, and `connected/{n}` or `disconnected/{n}`
+/// with the number of the connection for the event websocket.
+pub(crate) fn core_event(notice_id: String, time: u64) -> EventStructure {
+    EventStructure {
+        r#type: EventType::CoreEvent.into(),
+        event: Some(event_structure::Event::Notice(NoticeEvent {
+            r#type: NoticeType::Unknown.into(),
+            time,
+            notice_id,
+            notice: None,
+        })),
+    }
+}
+
+/// The core event of the `connection`th event websocket connecting.
+pub(crate) fn connected_event(connection: u64) -> EventStructure {
+    core_event(format!("connected/{}", connection), now())
+}
+
+/// The core event of the `connection`th event websocket disconnecting.
+pub(crate) fn disconnected_event(connection: u64) -> EventStructure {
+    core_event(format!("disconnected/{}", connection), now())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_link_state() {
+        let link = LinkState::default();
+        assert!(!link.is_connected());
+        assert_eq!(link.last_connected(), None);
+        assert_eq!(link.connect(), 1);
+        link.disconnect();
+        assert_eq!(link.connect(), 2);
+        assert!(link.is_connected());
+        assert!(link.last_connected().is_some());
+        assert_eq!(link.reconnects(), 1);
+    }
+
+    #[test]
+    fn test_connection_events() {
+        let notice_id = |event: EventStructure| match event.event {
+            Some(event_structure::Event::Notice(x)) => x.notice_id,
+            x => panic!("not a notice: {:?}", x),
+        };
+        assert_eq!(notice_id(connected_event(2)), "connected/2");
+        assert_eq!(notice_id(disconnected_event(2)), "disconnected/2");
+    }
+}
@@ -0,0 +1,140 @@
+use std::sync::Mutex;
+
+use super::schema::{Event, Login, Status};
+
+/// Logins of the satori server, as told by READY and kept up to date by
+/// `login-added`, `login-removed` and `login-updated` events.
+#[derive(Default)]
+pub struct LoginRegistry {
+    logins: Mutex<Vec<Login>>,
+}
+
+fn same_login(a: &Login, b: &Login) -> bool {
+    a.platform == b.platform && a.self_id == b.self_id
+}
+
+impl LoginRegistry {
+    /// Replace all the logins, on READY.
+    pub fn reset(&self, logins: Vec<Login>) {
+        *self.logins.lock().unwrap() = logins;
+    }
+    /// Apply a login event, returning whether it was one.
+    pub fn apply(&self, event: &Event) -> bool {
+        let login = match event._type.as_str() {
+            "login-added" | "login-removed" | "login-updated" => Login {
+                platform: Some(event.platform.clone()),
+                self_id: Some(event.self_id.clone()),
+                ..event.login.clone().unwrap_or(Login {
+                    user: None,
+                    self_id: None,
+                    platform: None,
+                    status: Status::OFFLINE,
+                })
+            },
+            _ => return false,
+        };
+        let mut logins = self.logins.lock().unwrap();
+        logins.retain(|x| !same_login(x, &login));
+        if event._type != "login-removed" {
+            logins.push(login);
+        }
+        true
+    }
+    pub fn find(&self, self_id: &str) -> Option<Login> {
+        self.logins
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|x| x.self_id.as_deref() == Some(self_id))
+            .cloned()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.logins.lock().unwrap().is_empty()
+    }
+    pub fn all(&self) -> Vec<Login> {
+        self.logins.lock().unwrap().clone()
+    }
+    /// The login of the account selected, or the current one if none is.
+    ///
+    /// Fails with the account if the satori server has no login of it.
+    /// Servers that never tell their logins still get the login asked for.
+    pub fn select(
+        &self,
+        self_id: Option<String>,
+        platform: Option<String>,
+    ) -> Result<Option<Login>, String> {
+        let Some(self_id) = self_id else {
+            return Ok(self.current());
+        };
+        if let Some(login) = self.find(&self_id) {
+            return Ok(Some(login));
+        }
+        if !self.is_empty() {
+            return Err(self_id);
+        }
+        Ok(Some(Login {
+            user: None,
+            platform,
+            self_id: Some(self_id),
+            status: Status::ONLINE,
+        }))
+    }
+    /// The login to act as, preferring online ones.
+    pub fn current(&self) -> Option<Login> {
+        let logins = self.logins.lock().unwrap();
+        logins
+            .iter()
+            .find(|x| x.status == Status::ONLINE)
+            .or(logins.first())
+            .cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_login_events() {
+        let registry = LoginRegistry::default();
+        registry.reset(
+            serde_json::from_value(json!([
+                {"platform": "qq", "self_id": "1", "status": 3},
+                {"platform": "qq", "self_id": "2", "status": 1},
+            ]))
+            .unwrap(),
+        );
+        assert_eq!(registry.current().unwrap().self_id.as_deref(), Some("2"));
+        let event = |r#type: &str, status: u8| -> Event {
+            serde_json::from_value(json!({
+                "id": 1,
+                "type": r#type,
+                "platform": "qq",
+                "self_id": "2",
+                "timestamp": 0,
+                "login": {"status": status},
+            }))
+            .unwrap()
+        };
+        assert!(registry.apply(&event("login-updated", 0)));
+        assert_eq!(registry.all().len(), 2);
+        assert!(registry.apply(&event("login-removed", 0)));
+        assert_eq!(registry.current().unwrap().self_id.as_deref(), Some("1"));
+        assert!(registry.find("1").is_some());
+        assert!(registry.find("2").is_none());
+        // a selected login that is gone is not replaced by another one.
+        assert_eq!(registry.select(Some("2".into()), None).unwrap_err(), "2");
+        assert_eq!(
+            registry
+                .select(None, None)
+                .unwrap()
+                .unwrap()
+                .self_id
+                .as_deref(),
+            Some("1")
+        );
+        registry.reset(Vec::new());
+        assert!(registry.select(Some("2".into()), None).unwrap().is_some());
+    }
+}
@@ -0,0 +1,251 @@
+//! Degradation of kritor elements that satori is unable to express.
+//!
+//! Instead of failing the whole message, such elements may be dropped or replaced
+//! by a readable text, as configured per element type.
+use std::collections::HashMap;
+
+use kritor::common::element::Data as KritorElementData;
+use kritor::common::element::ElementType as KritorElementType;
+use kritor::common::music_element::Data as MusicData;
+use kritor::common::{Element as KritorElement, Scene, TextElement};
+
+use super::{element::Element, Context, Error};
+
+/// What to do with an element satori cannot express.
+#[derive(Debug, Default, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Policy {
+    /// Fail the whole message.
+    #[default]
+    Reject,
+    /// Leave the element out.
+    Drop,
+    /// Replace the element with a short description, e.g. `[音乐] title`.
+    Text,
+    /// Like `text`, but followed by a jump url if the element has one.
+    Link,
+}
+
+#[derive(Debug, Default, Clone, serde::Serialize, serde::Deserialize)]
+pub struct DegradeConfig {
+    /// policy for element types not listed.
+    #[serde(default)]
+    pub default: Policy,
+    /// policies keyed by kritor element type in snake case, e.g. `music` or `market_face`.
+    #[serde(flatten)]
+    pub elements: HashMap<String, Policy>,
+}
+
+/// An element that has been degraded when sending a message.
+#[derive(Debug)]
+pub struct Degraded {
+    /// position of the message in a forward bundle.
+    pub message: Option<usize>,
+    /// position of the element in the original message.
+    pub index: usize,
+    pub r#type: KritorElementType,
+    pub policy: Policy,
+}
+
+impl std::fmt::Display for Degraded {
+    /// Formatted as `{index}:{type}:{policy}`, e.g. `2:music:link`, with the
+    /// index prefixed by that of the message in a bundle, e.g. `1.2:music:link`.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(message) = self.message {
+            write!(f, "{}.", message)?;
+        }
+        write!(
+            f,
+            "{}:{}:{}",
+            self.index,
+            type_name(self.r#type),
+            format!("{:?}", self.policy).to_lowercase()
+        )
+    }
+}
+
+impl DegradeConfig {
+    pub fn policy(&self, r#type: KritorElementType) -> Policy {
+        self.elements
+            .get(&type_name(r#type))
+            .copied()
+            .unwrap_or(self.default)
+    }
+    /// Apply the policies to the elements that fail to convert as unsupported
+    /// by satori and the dialect of the context.
+    ///
+    /// `message` is the position of the message in a forward bundle.
+    /// Returns the resulting elements and what has been degraded,
+    /// or an error if any of them is to be rejected or malformed.
+    pub fn apply(
+        &self,
+        elements: Vec<KritorElement>,
+        message: Option<usize>,
+        ctx: &Context,
+    ) -> Result<(Vec<KritorElement>, Vec<Degraded>), Error> {
+        let mut result = Vec::with_capacity(elements.len());
+        let mut degraded = Vec::new();
+        for (index, element) in elements.into_iter().enumerate() {
+            let r#type = match Element::from_kritor(element.clone(), ctx) {
+                Ok(_) => {
+                    result.push(element);
+                    continue;
+                }
+                Err(Error::Unsupported(r#type)) => r#type,
+                Err(e) => return Err(e),
+            };
+            let policy = self.policy(r#type);
+            match policy {
+                Policy::Reject => return Err(Error::Unsupported(r#type)),
+                Policy::Drop => {}
+                Policy::Text | Policy::Link => result.push(KritorElement {
+                    r#type: KritorElementType::Text.into(),
+                    data: Some(KritorElementData::Text(TextElement {
+                        text: fallback_text(r#type, element.data, policy == Policy::Link),
+                    })),
+                }),
+            }
+            degraded.push(Degraded {
+                message,
+                index,
+                r#type,
+                policy,
+            });
+        }
+        Ok((result, degraded))
+    }
+}
+
+fn type_name(r#type: KritorElementType) -> String {
+    r#type.as_str_name().to_lowercase()
+}
+
+/// kritor music platforms with a known jump url.
+const MUSIC_PLATFORM_QQ: i32 = 0;
+const MUSIC_PLATFORM_NETEASE: i32 = 1;
+
+/// Describe an element in text, optionally followed by its jump url.
+fn fallback_text(r#type: KritorElementType, data: Option<KritorElementData>, link: bool) -> String {
+    let (text, url) = match data {
+        Some(KritorElementData::Music(x)) => match x.data {
+            Some(MusicData::Custom(x)) => (format!("[音乐] {} - {}", x.title, x.author), x.url),
+            Some(MusicData::Id(id)) => match x.platform {
+                MUSIC_PLATFORM_QQ => (
+                    "[QQ音乐]".into(),
+                    format!("https://i.y.qq.com/v8/playsong.html?songid={}", id),
+                ),
+                MUSIC_PLATFORM_NETEASE => (
+                    "[网易云音乐]".into(),
+                    format!("https://music.163.com/song?id={}", id),
+                ),
+                _ => ("[音乐]".into(), String::new()),
+            },
+            None => ("[音乐]".into(), String::new()),
+        },
+        Some(KritorElementData::Share(x)) => (format!("[分享] {}", x.title), x.url),
+        Some(KritorElementData::Location(x)) => (
+            format!("[位置] {} {}", x.title, x.address),
+            format!("https://uri.amap.com/marker?position={},{}", x.lon, x.lat),
+        ),
+        Some(KritorElementData::Weather(x)) => (format!("[天气] {}", x.city), String::new()),
+        Some(KritorElementData::Contact(x)) => (
+            if x.scene == i32::from(Scene::Group) {
+                format!("[推荐群聊] {}", x.peer)
+            } else {
+                format!("[推荐联系人] {}", x.peer)
+            },
+            String::new(),
+        ),
+        Some(KritorElementData::File(x)) => (
+            format!("[文件] {}", x.name.unwrap_or_default()),
+            x.url.unwrap_or_default(),
+        ),
+        _ => (
+            match r#type {
+                KritorElementType::Poke => "[戳一戳]",
+                KritorElementType::Dice => "[骰子]",
+                KritorElementType::Rps => "[猜拳]",
+                KritorElementType::Basketball => "[篮球]",
+                KritorElementType::MarketFace => "[商城表情]",
+                KritorElementType::BubbleFace => "[表情]",
+                KritorElementType::Gift => "[礼物]",
+                KritorElementType::Json | KritorElementType::Xml => "[卡片消息]",
+                KritorElementType::Reply => "[回复]",
+                _ => "[不支持的消息]",
+            }
+            .into(),
+            String::new(),
+        ),
+    };
+    let text = text.trim_end().to_string();
+    if link && !url.is_empty() {
+        format!("{} {}", text, url)
+    } else {
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{dialect::Dialect, Fixture};
+    use super::*;
+    use kritor::common::{CustomMusicData, MusicElement, PokeElement};
+
+    #[test]
+    fn test_apply() {
+        let config = DegradeConfig {
+            default: Policy::Reject,
+            elements: HashMap::from([
+                ("music".to_string(), Policy::Link),
+                ("poke".to_string(), Policy::Drop),
+            ]),
+        };
+        let music = KritorElement {
+            r#type: KritorElementType::Music.into(),
+            data: Some(KritorElementData::Music(MusicElement {
+                data: Some(MusicData::Custom(CustomMusicData {
+                    url: "https://a33.su".into(),
+                    title: "title".into(),
+                    author: "author".into(),
+                    ..Default::default()
+                })),
+                ..Default::default()
+            })),
+        };
+        let poke = KritorElement {
+            r#type: KritorElementType::Poke.into(),
+            data: Some(KritorElementData::Poke(PokeElement::default())),
+        };
+        let fixture = Fixture::default();
+        let standard = fixture.context(Dialect::Standard);
+        let (elements, degraded) = config
+            .apply(vec![music, poke.clone()], None, &standard)
+            .unwrap();
+        assert_eq!(elements.len(), 1);
+        assert!(matches!(
+            &elements[0].data,
+            Some(KritorElementData::Text(x)) if x.text == "[音乐] title - author https://a33.su"
+        ));
+        let degraded: Vec<_> = degraded.iter().map(ToString::to_string).collect();
+        assert_eq!(degraded, ["0:music:link", "1:poke:drop"]);
+        let (_, degraded) = config
+            .apply(vec![poke.clone()], Some(2), &standard)
+            .unwrap();
+        assert_eq!(degraded[0].to_string(), "2.0:poke:drop");
+
+        assert!(DegradeConfig::default()
+            .apply(vec![poke.clone()], None, &standard)
+            .is_err());
+        let (elements, degraded) = DegradeConfig::default()
+            .apply(vec![poke], None, &fixture.context(Dialect::Chronocat))
+            .unwrap();
+        assert_eq!(elements.len(), 1);
+        assert!(degraded.is_empty());
+        // malformed elements are not degraded.
+        let text = KritorElement {
+            r#type: KritorElementType::Text.into(),
+            data: None,
+        };
+        assert!(config.apply(vec![text], None, &standard).is_err());
+    }
+}
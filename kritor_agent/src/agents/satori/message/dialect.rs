@@ -0,0 +1,281 @@
+//! Vendor elements of satori implementations.
+//!
+//! Some implementations carry QQ specific features as namespaced elements of
+//! their own. With the matching dialect selected, kritor elements without a
+//! standard satori equivalent are converted to and from these:
+//!
+//! | dialect | kritor | element | attributes / content |
+//! |---------|--------|---------|----------------------|
+//! | chronocat, lagrange | Poke | `{dialect}:poke` | `id`, `type`, `strength` |
+//! | chronocat | MarketFace | `chronocat:marketface` | `tab-id`, `face-id`, `key` |
+//! | lagrange | MarketFace | `lagrange:marketface` | `id` |
+//! | chronocat, lagrange | Dice | `{dialect}:dice` | `id` |
+//! | chronocat, lagrange | Rps | `{dialect}:rps` | `id` |
+//! | chronocat, qq, lagrange | Json | `{dialect}:json` | the json as content |
+//! | chronocat, lagrange | Xml | `{dialect}:xml` | the xml as content |
+//!
+//! kritor market faces only have an id, which is `{tab-id}:{face-id}:{key}`
+//! for chronocat ones. The content of cards is escaped, as xml cards are
+//! markup themselves.
+use std::collections::HashMap;
+
+use kritor::common::element::Data as KritorElementData;
+use kritor::common::element::ElementType as KritorElementType;
+use kritor::common::{
+    DiceElement, Element as KritorElement, JsonElement, MarketFaceElement, PokeElement, RpsElement,
+    XmlElement,
+};
+
+use super::{element::*, Error};
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Dialect {
+    /// Standard satori elements only.
+    #[default]
+    Standard,
+    /// [chronocat](https://chronocat.vercel.app), `chronocat:` elements.
+    Chronocat,
+    /// koishi adapter-qq for QQ official bots, `qq:` elements.
+    Qq,
+    /// Lagrange, `lagrange:` elements.
+    Lagrange,
+}
+
+impl Dialect {
+    /// The vendor elements of the dialect, by their names.
+    fn elements(self) -> &'static [(&'static str, KritorElementType)] {
+        match self {
+            Dialect::Standard => &[],
+            Dialect::Chronocat => &[
+                ("chronocat:poke", KritorElementType::Poke),
+                ("chronocat:marketface", KritorElementType::MarketFace),
+                ("chronocat:dice", KritorElementType::Dice),
+                ("chronocat:rps", KritorElementType::Rps),
+                ("chronocat:json", KritorElementType::Json),
+                ("chronocat:xml", KritorElementType::Xml),
+            ],
+            // official bots are only able to send ark cards.
+            Dialect::Qq => &[("qq:json", KritorElementType::Json)],
+            Dialect::Lagrange => &[
+                ("lagrange:poke", KritorElementType::Poke),
+                ("lagrange:marketface", KritorElementType::MarketFace),
+                ("lagrange:dice", KritorElementType::Dice),
+                ("lagrange:rps", KritorElementType::Rps),
+                ("lagrange:json", KritorElementType::Json),
+                ("lagrange:xml", KritorElementType::Xml),
+            ],
+        }
+    }
+    /// Whether the dialect has an element for a kritor type that standard satori lacks.
+    fn supports(self, r#type: KritorElementType) -> bool {
+        self.elements().iter().any(|(_, x)| *x == r#type)
+    }
+    /// Whether the kritor element should be converted with [Dialect::from_kritor].
+    pub fn handles(self, element: &KritorElement) -> bool {
+        KritorElementType::try_from(element.r#type).is_ok_and(|x| self.supports(x))
+    }
+    /// Convert a kritor element into the vendor element.
+    pub fn from_kritor(self, element: KritorElement) -> Result<Element, Error> {
+        let r#type = KritorElementType::try_from(element.r#type).map_err(|_| "Invalid type")?;
+        let name = self
+            .elements()
+            .iter()
+            .find(|(_, x)| *x == r#type)
+            .map(|(name, _)| *name)
+            .ok_or(Error::Unsupported(r#type))?;
+        let mut attributes = HashMap::new();
+        let mut content = None;
+        match (self, element.data.ok_or("Missing data")?) {
+            (_, KritorElementData::Poke(x)) => {
+                attributes.insert("id".to_string(), x.id.to_string());
+                attributes.insert("type".to_string(), x.poke_type.to_string());
+                attributes.insert("strength".to_string(), x.strength.to_string());
+            }
+            (Dialect::Chronocat, KritorElementData::MarketFace(x)) => {
+                let mut parts = x.id.splitn(3, ':');
+                let (Some(tab_id), Some(face_id), Some(key)) =
+                    (parts.next(), parts.next(), parts.next())
+                else {
+                    return Err("market face id should be {tab-id}:{face-id}:{key}".into());
+                };
+                attributes.insert("tab-id".to_string(), tab_id.to_string());
+                attributes.insert("face-id".to_string(), face_id.to_string());
+                attributes.insert("key".to_string(), key.to_string());
+            }
+            (_, KritorElementData::MarketFace(x)) => {
+                attributes.insert("id".to_string(), x.id);
+            }
+            (_, KritorElementData::Dice(x)) => {
+                attributes.insert("id".to_string(), x.id.to_string());
+            }
+            (_, KritorElementData::Rps(x)) => {
+                attributes.insert("id".to_string(), x.id.to_string());
+            }
+            (_, KritorElementData::Json(x)) => content = Some(x.json),
+            (_, KritorElementData::Xml(x)) => content = Some(x.xml),
+            _ => return Err("Invalid data".into()),
+        }
+        Ok(Element::from(TagElement {
+            name: name.to_string(),
+            attributes: (!attributes.is_empty()).then_some(attributes),
+            children: content.map(|x| vec![Element::new_plain(escape_content(&x))]),
+        }))
+    }
+    /// Convert a vendor element of this dialect into a kritor element.
+    ///
+    /// Returns `None` if the element does not belong to the dialect.
+    pub fn into_kritor(self, tag: &TagElement) -> Option<Result<KritorElement, Error>> {
+        let (_, r#type) = self.elements().iter().find(|(name, _)| *name == tag.name)?;
+        let attr = |name: &str| {
+            tag.attributes
+                .as_ref()
+                .and_then(|x| x.get(name))
+                .cloned()
+                .unwrap_or_default()
+        };
+        let number = |name: &str| attr(name).parse::<u32>().unwrap_or_default();
+        let content = || {
+            unescape_content(
+                &tag.children
+                    .iter()
+                    .flatten()
+                    .filter_map(Element::text)
+                    .map(String::as_str)
+                    .collect::<String>(),
+            )
+        };
+        let data = match (self, r#type) {
+            (_, KritorElementType::Poke) => KritorElementData::Poke(PokeElement {
+                id: number("id"),
+                poke_type: number("type"),
+                strength: number("strength"),
+            }),
+            (Dialect::Chronocat, KritorElementType::MarketFace) => {
+                KritorElementData::MarketFace(MarketFaceElement {
+                    id: format!("{}:{}:{}", attr("tab-id"), attr("face-id"), attr("key")),
+                })
+            }
+            (_, KritorElementType::MarketFace) => {
+                KritorElementData::MarketFace(MarketFaceElement { id: attr("id") })
+            }
+            (_, KritorElementType::Dice) => {
+                KritorElementData::Dice(DiceElement { id: number("id") })
+            }
+            (_, KritorElementType::Rps) => KritorElementData::Rps(RpsElement { id: number("id") }),
+            (_, KritorElementType::Json) => {
+                KritorElementData::Json(JsonElement { json: content() })
+            }
+            (_, KritorElementType::Xml) => KritorElementData::Xml(XmlElement { xml: content() }),
+            _ => return Some(Err("Invalid type".into())),
+        };
+        Some(Ok(KritorElement {
+            r#type: (*r#type).into(),
+            data: Some(data),
+        }))
+    }
+}
+
+/// Escape the markup characters of card content, which is sent as text.
+fn escape_content(content: &str) -> String {
+    content
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// The inverse of [escape_content].
+fn unescape_content(content: &str) -> String {
+    content
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{Fixture, Parser};
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let fixture = Fixture::default();
+        let ctx = fixture.context(Dialect::Chronocat);
+        let elements = vec![
+            KritorElement {
+                r#type: KritorElementType::Poke.into(),
+                data: Some(KritorElementData::Poke(PokeElement {
+                    id: 1,
+                    poke_type: 2,
+                    strength: 3,
+                })),
+            },
+            KritorElement {
+                r#type: KritorElementType::MarketFace.into(),
+                data: Some(KritorElementData::MarketFace(MarketFaceElement {
+                    id: "1:2:abc".to_string(),
+                })),
+            },
+        ];
+        let content = Root::try_from_kritor_elements(elements.clone(), &ctx)
+            .unwrap()
+            .root_element
+            .serialize();
+        assert!(content.contains(r#"tab-id="1""#));
+        let parsed = Parser::new(&content)
+            .parse()
+            .unwrap()
+            .try_into_kritor_elements(&ctx)
+            .unwrap();
+        assert_eq!(parsed, elements);
+
+        // market faces of other ids can not be sent.
+        let face = KritorElement {
+            r#type: KritorElementType::MarketFace.into(),
+            data: Some(KritorElementData::MarketFace(MarketFaceElement {
+                id: "abc".to_string(),
+            })),
+        };
+        assert!(Dialect::Chronocat.from_kritor(face.clone()).is_err());
+
+        // lagrange sends them by id, and cards with markup as content.
+        let ctx = fixture.context(Dialect::Lagrange);
+        let elements = vec![
+            face,
+            KritorElement {
+                r#type: KritorElementType::Xml.into(),
+                data: Some(KritorElementData::Xml(XmlElement {
+                    xml: r#"<msg brief="&amp;"/>"#.to_string(),
+                })),
+            },
+            KritorElement {
+                r#type: KritorElementType::Dice.into(),
+                data: Some(KritorElementData::Dice(DiceElement { id: 3 })),
+            },
+        ];
+        let lagrange = Root::try_from_kritor_elements(elements.clone(), &ctx)
+            .unwrap()
+            .root_element
+            .serialize();
+        assert!(lagrange.contains(r#"<lagrange:marketface id="abc"/>"#));
+        let parsed = Parser::new(&lagrange)
+            .parse()
+            .unwrap()
+            .try_into_kritor_elements(&ctx)
+            .unwrap();
+        assert_eq!(parsed, elements);
+        // official bots only send json cards.
+        assert!(!Dialect::Qq.handles(&elements[2]));
+
+        // the standard dialect leaves them as text.
+        let ctx = fixture.context(Dialect::Standard);
+        let parsed = Parser::new(&content)
+            .parse()
+            .unwrap()
+            .try_into_kritor_elements(&ctx)
+            .unwrap();
+        assert!(parsed
+            .iter()
+            .all(|x| x.r#type == i32::from(KritorElementType::Text)));
+    }
+}
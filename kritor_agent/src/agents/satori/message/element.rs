@@ -1,7 +1,7 @@
 use std::collections::HashMap;
 use std::collections::VecDeque;
 
-use super::Error;
+use super::{forward, Context, Error};
 
 use base64::prelude::*;
 use kritor::common::element::Data as KritorElementData;
@@ -28,6 +28,15 @@ impl TagElement {
     pub fn attributes_mut(&mut self) -> &mut HashMap<String, String> {
         self.attributes.get_or_insert_with(HashMap::new)
     }
+    /// Whether this is a `<message forward>` element.
+    pub fn is_forward(&self) -> bool {
+        self.name == "message"
+            && self
+                .attributes
+                .as_ref()
+                .and_then(|x| x.get("forward"))
+                .is_some_and(|x| x != "false")
+    }
 }
 
 impl Element {
@@ -95,7 +104,7 @@ pub struct Root {
 }
 
 impl Root {
-    pub fn try_into_kritor_elements(self) -> Result<Vec<KritorElement>, Error> {
+    pub fn try_into_kritor_elements(self, ctx: &Context) -> Result<Vec<KritorElement>, Error> {
         match self.root_element {
             Element::Plain(text) => Ok(vec![KritorElement {
                 r#type: KritorElementType::Text.into(),
@@ -107,35 +116,83 @@ impl Root {
                 let mut result = Vec::new();
                 let mut queue = tag.children.map(VecDeque::from).unwrap_or_default();
                 while let Some(mut tag) = queue.pop_front() {
+                    // nested messages of a forward element must not be flattened.
+                    if tag.tag().is_some_and(TagElement::is_forward) {
+                        if let Element::Tag(tag) = tag {
+                            result.push(forward::into_kritor_forward(*tag, ctx)?);
+                        }
+                        continue;
+                    }
+                    if let Some(element) = tag.tag().and_then(|x| ctx.dialect.into_kritor(x)) {
+                        result.push(element?);
+                        continue;
+                    }
                     if let Some(ref mut children) = tag.tag_mut().and_then(|x| x.children.as_mut())
                     {
                         for child in children.drain(..) {
                             queue.push_back(child);
                         }
                     }
-                    result.push(tag.try_into()?);
+                    let at = tag
+                        .tag()
+                        .filter(|x| x.name == "at")
+                        .and_then(|x| x.attributes.as_ref()?.get("id").cloned());
+                    let mut element: KritorElement = tag.try_into()?;
+                    // the platform id is kept as the uid, as it may not be numeric.
+                    if let (Some(id), Some(KritorElementData::At(x))) = (at, &mut element.data) {
+                        x.uin = Some(ctx.identities.uin(&id));
+                        x.uid = Some(id);
+                    }
+                    result.push(element);
                 }
                 Ok(result)
             }
         }
     }
-    pub fn try_from_kritor_elements(value: Vec<KritorElement>) -> Result<Self, Error> {
+    pub fn try_from_kritor_elements(
+        value: Vec<KritorElement>,
+        ctx: &Context,
+    ) -> Result<Self, Error> {
         let root_element = Element::from(TagElement {
             name: "".into(),
             attributes: None,
-            children: {
-                let mut result = Vec::new();
-                for element in value.into_iter() {
-                    result.push(element.try_into()?)
-                }
-                Some(result)
-            },
+            children: Some(
+                value
+                    .into_iter()
+                    .map(|x| Element::from_kritor(x, ctx))
+                    .collect::<Result<_, _>>()?,
+            ),
         });
         Ok(Root { root_element })
     }
 }
 
 impl Element {
+    /// Convert a kritor element, failing with [Error::Unsupported] if neither
+    /// satori nor the dialect is able to express it.
+    pub fn from_kritor(element: KritorElement, ctx: &Context) -> Result<Self, Error> {
+        match element {
+            KritorElement {
+                data: Some(KritorElementData::Forward(x)),
+                ..
+            } => forward::from_kritor_forward(x, ctx),
+            element if ctx.dialect.handles(&element) => ctx.dialect.from_kritor(element),
+            KritorElement {
+                r#type,
+                data: Some(KritorElementData::At(mut x)),
+            } => {
+                if x.uid.is_none() {
+                    x.uid = x.uin.and_then(|uin| ctx.identities.uid(uin));
+                }
+                KritorElement {
+                    r#type,
+                    data: Some(KritorElementData::At(x)),
+                }
+                .try_into()
+            }
+            element => element.try_into(),
+        }
+    }
     /// Serialize a element into the satori message representation.
     pub fn serialize(&self) -> String {
         match self {
@@ -213,6 +270,7 @@ impl TryFrom<Element> for kritor::common::Element {
                         Some(KritorElementData::Image(kritor::common::ImageElement {
                             file_type: Some(kritor_image::ImageType::Common.into()),
                             data: Some(kritor_image::Data::FileUrl(x.remove("src")?)),
+                            // filled in later if computing md5 is enabled.
                             file_md5: None,
                             sub_type: None,
                         }))
@@ -251,22 +309,17 @@ impl TryFrom<Element> for kritor::common::Element {
                         })
                     }),
                 },
-                "message"
-                    if tag
-                        .attributes
-                        .as_ref()
-                        .is_some_and(|x| x.get("forward").is_some_and(|x| x == "true")) =>
-                {
-                    KritorElement {
-                        r#type: KritorElementType::Forward.into(),
-                        data: tag.attributes.and_then(|mut x| {
-                            Some(KritorElementData::Forward(kritor::common::ForwardElement {
-                                uniseq: x.remove("id")?,
-                                ..Default::default()
-                            }))
-                        }),
-                    }
-                }
+                "message" if tag.is_forward() => KritorElement {
+                    r#type: KritorElementType::Forward.into(),
+                    data: tag.attributes.and_then(|mut x| {
+                        let id = x.remove("id")?;
+                        Some(KritorElementData::Forward(kritor::common::ForwardElement {
+                            res_id: id.clone(),
+                            uniseq: id,
+                            ..Default::default()
+                        }))
+                    }),
+                },
                 _ => KritorElement {
                     r#type: KritorElementType::Text.into(),
                     data: Some(KritorElementData::Text(kritor::common::TextElement {
@@ -302,13 +355,19 @@ impl TryFrom<KritorElement> for Element {
                     name: "at".to_string(),
                     attributes: Some({
                         let mut map = HashMap::new();
-                        if data.uid == Some("all".to_string()) {
-                            map.insert("type".to_string(), "all".to_string());
-                        } else {
-                            map.insert(
-                                "id".to_string(),
-                                data.uin.ok_or("Missing uin")?.to_string(),
-                            );
+                        match data.uid {
+                            Some(uid) if uid == "all" => {
+                                map.insert("type".to_string(), "all".to_string());
+                            }
+                            Some(uid) => {
+                                map.insert("id".to_string(), uid);
+                            }
+                            None => {
+                                map.insert(
+                                    "id".to_string(),
+                                    data.uin.ok_or("Missing uin")?.to_string(),
+                                );
+                            }
                         }
                         map
                     }),
@@ -423,13 +482,116 @@ impl TryFrom<KritorElement> for Element {
                     children: None,
                 })
             }
-            _ => {
-                return Err("including elements unsupported by satori.".into());
+            KritorElementType::Forward => {
+                let data = value.data.ok_or("Missing data")?;
+                let data = match data {
+                    KritorElementData::Forward(x) => x,
+                    _ => return Err("Invalid data".into()),
+                };
+                // refer to the forward message by id and let the satori server resolve it.
+                let id = if data.res_id.is_empty() {
+                    data.uniseq
+                } else {
+                    data.res_id
+                };
+                if id.is_empty() {
+                    return Err("Missing res_id".into());
+                }
+                Element::from(TagElement {
+                    name: "message".to_string(),
+                    attributes: Some({
+                        let mut map = HashMap::new();
+                        map.insert("id".to_string(), id);
+                        map.insert("forward".to_string(), "true".to_string());
+                        map
+                    }),
+                    children: None,
+                })
             }
+            KritorElementType::Markdown => {
+                let data = value.data.ok_or("Missing data")?;
+                let data = match data {
+                    KritorElementData::Markdown(x) => x,
+                    _ => return Err("Invalid data".into()),
+                };
+                // satori has no markdown element, so the source is sent as is.
+                Element::new_plain(data.markdown)
+            }
+            KritorElementType::Keyboard => {
+                let data = value.data.ok_or("Missing data")?;
+                let data = match data {
+                    KritorElementData::Keyboard(x) => x,
+                    _ => return Err("Invalid data".into()),
+                };
+                let mut children = Vec::new();
+                for (i, row) in data.rows.into_iter().enumerate() {
+                    if i > 0 {
+                        children.push(Element::with_tag_name("br".to_string()));
+                    }
+                    children.extend(row.buttons.into_iter().map(button_element));
+                }
+                // a tag without name serializes to its children only.
+                Element::from(TagElement {
+                    name: String::new(),
+                    attributes: None,
+                    children: Some(children),
+                })
+            }
+            r#type => return Err(Error::Unsupported(r#type)),
         })
     }
 }
 
+/// kritor button action types, as defined by QQ bot keyboards.
+const BUTTON_ACTION_LINK: i32 = 0;
+const BUTTON_ACTION_COMMAND: i32 = 2;
+/// kritor button render style for a blue button, others are grey.
+const BUTTON_STYLE_BLUE: i32 = 1;
+
+/// Convert a kritor keyboard button into a satori `<button>`.
+///
+/// The satori id of a button is its kritor id, which `interaction/button` events
+/// report back, or the action data of callback buttons without one.
+fn button_element(button: kritor::common::Button) -> Element {
+    let action = button.action.unwrap_or_default();
+    let render = button.render_data.unwrap_or_default();
+    let mut map = HashMap::new();
+    match action.r#type {
+        BUTTON_ACTION_LINK => {
+            map.insert("type".to_string(), "link".to_string());
+            map.insert("href".to_string(), action.data);
+        }
+        BUTTON_ACTION_COMMAND => {
+            map.insert("type".to_string(), "input".to_string());
+            map.insert("text".to_string(), action.data);
+        }
+        // callback buttons, unknown types are treated as such too.
+        _ => {
+            map.insert("type".to_string(), "action".to_string());
+            if button.id.is_empty() && !action.data.is_empty() {
+                map.insert("id".to_string(), action.data);
+            }
+        }
+    }
+    if !button.id.is_empty() {
+        map.insert("id".to_string(), button.id);
+    }
+    map.insert(
+        "theme".to_string(),
+        if render.style == BUTTON_STYLE_BLUE {
+            "primary"
+        } else {
+            "secondary"
+        }
+        .to_string(),
+    );
+    Element::from(TagElement {
+        name: "button".to_string(),
+        attributes: Some(map),
+        children: Some(vec![Element::new_plain(render.label)]),
+    })
+}
+
 fn encode_data_url(data: &[u8]) -> String {
     let mime = infer::get(data)
         .map(|x| x.mime_type())
@@ -441,6 +603,7 @@ fn encode_data_url(data: &[u8]) -> String {
 
 #[cfg(test)]
 mod tests {
+    use super::super::Fixture;
     use super::*;
 
     #[test]
@@ -471,10 +634,96 @@ mod tests {
         .parse()
         .unwrap();
         dbg!(&msg.root_element);
-        let kritor_element: Vec<KritorElement> = msg.try_into_kritor_elements().unwrap();
+        let fixture = Fixture::default();
+        let kritor_element: Vec<KritorElement> = msg
+            .try_into_kritor_elements(&fixture.context(Default::default()))
+            .unwrap();
         dbg!(&kritor_element);
     }
 
+    #[test]
+    fn test_keyboard_to_buttons() {
+        let button = |id: &str, r#type, data: &str| kritor::common::Button {
+            id: id.to_string(),
+            render_data: Some(kritor::common::ButtonRender {
+                label: id.to_string(),
+                ..Default::default()
+            }),
+            action: Some(kritor::common::ButtonAction {
+                r#type,
+                data: data.to_string(),
+                ..Default::default()
+            }),
+        };
+        let keyboard = KritorElement {
+            r#type: KritorElementType::Keyboard.into(),
+            data: Some(KritorElementData::Keyboard(
+                kritor::common::KeyboardElement {
+                    rows: vec![
+                        kritor::common::KeyboardRow {
+                            buttons: vec![
+                                button("a", 1, "cb"),
+                                button("b", BUTTON_ACTION_LINK, "https://a33.su"),
+                            ],
+                        },
+                        kritor::common::KeyboardRow {
+                            buttons: vec![button("c", BUTTON_ACTION_COMMAND, "/help")],
+                        },
+                    ],
+                    ..Default::default()
+                },
+            )),
+        };
+        let element = Element::try_from(keyboard).unwrap();
+        let children = element.tag().unwrap().children.as_ref().unwrap();
+        let names: Vec<_> = children
+            .iter()
+            .map(|x| x.tag().unwrap().name.as_str())
+            .collect();
+        assert_eq!(names, ["button", "button", "br", "button"]);
+        let attrs = |i: usize| children[i].tag().unwrap().attributes.as_ref().unwrap();
+        assert_eq!(attrs(0)["type"], "action");
+        // the kritor id is what clicks report back.
+        assert_eq!(attrs(0)["id"], "a");
+        assert_eq!(attrs(1)["href"], "https://a33.su");
+        assert_eq!(attrs(3)["text"], "/help");
+        let element = button_element(button("", 1, "cb"));
+        assert_eq!(
+            element.tag().unwrap().attributes.as_ref().unwrap()["id"],
+            "cb"
+        );
+    }
+
+    #[test]
+    fn test_media_metadata() {
+        // satori has no attributes for md5, sub type and image type.
+        let image = KritorElement {
+            r#type: KritorElementType::Image.into(),
+            data: Some(KritorElementData::Image(kritor::common::ImageElement {
+                file_type: Some(kritor_image::ImageType::Flash.into()),
+                data: Some(kritor_image::Data::FileUrl("https://a33.su/a.png".into())),
+                file_md5: Some("abc".into()),
+                sub_type: Some(1),
+            })),
+        };
+        let element = Element::try_from(image).unwrap();
+        assert_eq!(element.serialize(), r#"<img src="https://a33.su/a.png"/>"#);
+
+        let fixture = Fixture::default();
+        let elements = super::super::Parser::new(&element.serialize())
+            .parse()
+            .unwrap()
+            .try_into_kritor_elements(&fixture.context(Default::default()))
+            .unwrap();
+        match &elements[0].data {
+            Some(KritorElementData::Image(x)) => {
+                assert_eq!(x.file_type, Some(kritor_image::ImageType::Common.into()));
+                assert_eq!(x.file_md5, None);
+            }
+            x => panic!("not an image: {:?}", x),
+        }
+    }
+
     #[test]
     fn test_encode_data_url() {
         let data = b"Hello, world!";
@@ -0,0 +1,226 @@
+//! Conversion between kritor forward bundles and satori `<message forward>` trees.
+//!
+//! A forward message in satori is a `<message forward>` element whose children are
+//! `<message>` elements, each either referring to an existing message by `id`
+//! or carrying its own content and an optional `<author>`.
+use std::collections::HashMap;
+
+use kritor::common::element::Data as KritorElementData;
+use kritor::common::element::ElementType as KritorElementType;
+use kritor::common::forward_message_body::ForwardMessage;
+use kritor::common::push_message_body::Sender;
+use kritor::common::{
+    Element as KritorElement, ForwardElement, ForwardMessageBody, PrivateSender, PushMessageBody,
+};
+
+use super::{element::*, Context, Error};
+
+/// Convert a kritor forward element into a satori one.
+///
+/// If the res_id refers to a bundle kept by the agent, the bundle is expanded into
+/// nested messages. Otherwise it is sent as a reference for the satori server to resolve.
+pub fn from_kritor_forward(forward: ForwardElement, ctx: &Context) -> Result<Element, Error> {
+    match ctx.resources.get_forward(&forward.res_id) {
+        Some(bundle) => to_forward_element(bundle, ctx),
+        None => Element::try_from(KritorElement {
+            r#type: KritorElementType::Forward.into(),
+            data: Some(KritorElementData::Forward(forward)),
+        }),
+    }
+}
+
+/// Build a `<message forward>` element from a forward bundle.
+pub fn to_forward_element(
+    bundle: Vec<ForwardMessageBody>,
+    ctx: &Context,
+) -> Result<Element, Error> {
+    let mut children = Vec::new();
+    for body in bundle {
+        children.push(
+            match body.forward_message.ok_or("Missing forward message")? {
+                ForwardMessage::MessageId(id) => Element::from(TagElement {
+                    name: "message".to_string(),
+                    attributes: Some(HashMap::from([("id".to_string(), id)])),
+                    children: None,
+                }),
+                ForwardMessage::Message(message) => to_message_element(message, ctx)?,
+            },
+        );
+    }
+    Ok(Element::from(TagElement {
+        name: "message".to_string(),
+        attributes: Some(HashMap::from([("forward".to_string(), "true".to_string())])),
+        children: Some(children),
+    }))
+}
+
+fn to_message_element(message: PushMessageBody, ctx: &Context) -> Result<Element, Error> {
+    let mut children = Vec::new();
+    if let Some(sender) = message.sender {
+        children.push(author_element(sender, ctx));
+    }
+    if let Element::Tag(root) = Root::try_from_kritor_elements(message.elements, ctx)?.root_element
+    {
+        children.extend(root.children.unwrap_or_default());
+    }
+    // a `<message>` with an id but no content refers to an existing message.
+    let attributes = (children.is_empty() && !message.message_id.is_empty())
+        .then(|| HashMap::from([("id".to_string(), message.message_id)]));
+    Ok(Element::from(TagElement {
+        name: "message".to_string(),
+        attributes,
+        children: (!children.is_empty()).then_some(children),
+    }))
+}
+
+fn author_element(sender: Sender, ctx: &Context) -> Element {
+    let (id, name) = match sender {
+        Sender::Private(x) => (ctx.identities.platform_id(x.uid, x.uin), x.nick),
+        Sender::Group(x) => (ctx.identities.platform_id(x.uid, x.uin), x.nick),
+        Sender::Guild(x) => (ctx.identities.platform_id(None, x.tiny_id), x.nick),
+    };
+    Element::from(TagElement {
+        name: "author".to_string(),
+        attributes: Some(HashMap::from([
+            ("id".to_string(), id),
+            ("name".to_string(), name),
+        ])),
+        children: None,
+    })
+}
+
+/// Convert a satori `<message forward>` element into a kritor forward element.
+///
+/// Nested messages are kept in the resource store under a generated res_id,
+/// so that they can be retrieved with `DownloadForwardMessage` later.
+pub fn into_kritor_forward(tag: TagElement, ctx: &Context) -> Result<KritorElement, Error> {
+    let id = tag
+        .attributes
+        .and_then(|mut x| x.remove("id"))
+        .unwrap_or_default();
+    let children = tag.children.unwrap_or_default();
+    let res_id = if children.iter().any(|x| !x.is_plain()) {
+        ctx.resources
+            .put_forward(parse_forward_children(children, ctx)?)
+    } else {
+        id.clone()
+    };
+    Ok(KritorElement {
+        r#type: KritorElementType::Forward.into(),
+        data: Some(KritorElementData::Forward(ForwardElement {
+            res_id,
+            uniseq: id,
+            ..Default::default()
+        })),
+    })
+}
+
+fn parse_forward_children(
+    children: Vec<Element>,
+    ctx: &Context,
+) -> Result<Vec<ForwardMessageBody>, Error> {
+    let mut result = Vec::new();
+    for child in children {
+        // whitespaces between the nested messages are ignored.
+        let tag = match child {
+            Element::Tag(tag) if tag.name == "message" => tag,
+            _ => continue,
+        };
+        let id = tag.attributes.and_then(|mut x| x.remove("id"));
+        let children = tag.children.unwrap_or_default();
+        if children.is_empty() {
+            if let Some(id) = id {
+                result.push(ForwardMessageBody {
+                    forward_message: Some(ForwardMessage::MessageId(id)),
+                });
+            }
+            continue;
+        }
+        let mut sender = None;
+        let mut content = Vec::new();
+        for child in children {
+            match child {
+                Element::Tag(tag) if tag.name == "author" => {
+                    sender = Some(author_to_sender(tag.attributes.unwrap_or_default(), ctx))
+                }
+                child => content.push(child),
+            }
+        }
+        let elements = Root {
+            root_element: Element::from(TagElement {
+                name: String::new(),
+                attributes: None,
+                children: Some(content),
+            }),
+        }
+        .try_into_kritor_elements(ctx)?;
+        result.push(ForwardMessageBody {
+            forward_message: Some(ForwardMessage::Message(PushMessageBody {
+                message_id: id.unwrap_or_default(),
+                sender,
+                elements,
+                ..Default::default()
+            })),
+        });
+    }
+    Ok(result)
+}
+
+fn author_to_sender(mut attributes: HashMap<String, String>, ctx: &Context) -> Sender {
+    let uid = attributes.remove("id");
+    Sender::Private(PrivateSender {
+        uin: uid
+            .as_deref()
+            .map(|x| ctx.identities.uin(x))
+            .unwrap_or_default(),
+        uid,
+        nick: attributes.remove("name").unwrap_or_default(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{Fixture, Parser};
+    use super::*;
+
+    #[test]
+    fn test_forward_round_trip() {
+        let fixture = Fixture::default();
+        let ctx = fixture.context(Default::default());
+        let input = r#"<message forward><message><author id="10086" name="foo"/>hello</message><message id="42"/></message>"#;
+        let elements = Parser::new(input)
+            .parse()
+            .unwrap()
+            .try_into_kritor_elements(&ctx)
+            .unwrap();
+        assert_eq!(elements.len(), 1);
+        let forward = match elements.into_iter().next().unwrap().data {
+            Some(KritorElementData::Forward(x)) => x,
+            x => panic!("not a forward element: {:?}", x),
+        };
+        let bundle = ctx.resources.get_forward(&forward.res_id).unwrap();
+        assert_eq!(bundle.len(), 2);
+
+        let element = from_kritor_forward(forward, &ctx).unwrap();
+        let tag = element.tag().unwrap();
+        assert_eq!(tag.name, "message");
+        let children = tag.children.as_ref().unwrap();
+        assert_eq!(children.len(), 2);
+        assert_eq!(children[1].serialize(), r#"<message id="42"/>"#);
+    }
+
+    #[test]
+    fn test_guild_author() {
+        let fixture = Fixture::default();
+        let ctx = fixture.context(Default::default());
+        let tiny_id = ctx.identities.uin("E6A3F0C2B1D4");
+        let sender = Sender::Guild(kritor::common::GuildSender {
+            tiny_id,
+            nick: "foo".into(),
+            ..Default::default()
+        });
+        let author = author_element(sender, &ctx);
+        let attributes = author.tag().unwrap().attributes.as_ref().unwrap();
+        assert_eq!(attributes["id"], "E6A3F0C2B1D4");
+    }
+}
@@ -0,0 +1,127 @@
+//! Media metadata that satori does not always provide.
+use std::time::Duration;
+
+use base64::Engine;
+use futures_util::StreamExt;
+use kritor::common::element::Data as KritorElementData;
+use kritor::common::image_element::Data as ImageData;
+use kritor::common::Element as KritorElement;
+
+/// How long to wait for the images of a message to be downloaded for their md5.
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+/// How many images of a message are downloaded at once.
+const CONCURRENT_FETCHES: usize = 4;
+
+/// Fill in the md5 of images that come without one, downloading them if necessary.
+///
+/// Images that fail to be fetched in time, or are larger than `max_size` bytes,
+/// are left untouched.
+pub async fn fill_image_md5(elements: &mut [KritorElement], http: &reqwest::Client, max_size: u64) {
+    let images = elements.iter_mut().filter_map(|x| match &mut x.data {
+        Some(KritorElementData::Image(x)) if x.file_md5.is_none() => Some(x),
+        _ => None,
+    });
+    let fill = futures_util::stream::iter(images).for_each_concurrent(
+        CONCURRENT_FETCHES,
+        |image| async move {
+            let md5 = match &image.data {
+                Some(ImageData::File(x)) => Some(format!("{:x}", md5::compute(x))),
+                Some(ImageData::FileUrl(url)) => match decode_data_url(url) {
+                    Some(x) => Some(format!("{:x}", md5::compute(x))),
+                    None => fetch_md5(url, http, max_size).await,
+                },
+                _ => None,
+            };
+            if md5.is_some() {
+                image.file_md5 = md5;
+            }
+        },
+    );
+    if tokio::time::timeout(FETCH_TIMEOUT, fill).await.is_err() {
+        log::warn!("Timed out fetching images for their md5");
+    }
+}
+
+fn decode_data_url(url: &str) -> Option<Vec<u8>> {
+    let (_, data) = url.strip_prefix("data:")?.split_once(";base64,")?;
+    base64::engine::general_purpose::STANDARD.decode(data).ok()
+}
+
+/// The md5 of a download, streamed so that no more than `max_size` bytes are read.
+async fn fetch_md5(url: &str, http: &reqwest::Client, max_size: u64) -> Option<String> {
+    let resp = http
+        .get(url)
+        .send()
+        .await
+        .and_then(|x| x.error_for_status());
+    let mut resp = match resp {
+        Ok(resp) => resp,
+        Err(e) => {
+            log::warn!("Failed to fetch image {} for its md5: {}", url, e);
+            return None;
+        }
+    };
+    let mut context = md5::Context::new();
+    let mut size = 0;
+    loop {
+        match resp.chunk().await {
+            Ok(Some(chunk)) => {
+                size += chunk.len() as u64;
+                if size > max_size {
+                    log::warn!("Image {} is larger than {} bytes, no md5", url, max_size);
+                    return None;
+                }
+                context.consume(&chunk);
+            }
+            Ok(None) => return Some(format!("{:x}", context.compute())),
+            Err(e) => {
+                log::warn!("Failed to fetch image {} for its md5: {}", url, e);
+                return None;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kritor::common::element::ElementType as KritorElementType;
+    use kritor::common::ImageElement;
+
+    #[tokio::test]
+    async fn test_fill_data_url_md5() {
+        let mut elements = vec![KritorElement {
+            r#type: KritorElementType::Image.into(),
+            data: Some(KritorElementData::Image(ImageElement {
+                data: Some(ImageData::FileUrl(
+                    "data:image/png;base64,SGVsbG8sIHdvcmxkIQ==".into(),
+                )),
+                ..Default::default()
+            })),
+        }];
+        fill_image_md5(&mut elements, &reqwest::Client::new(), 1024).await;
+        assert!(matches!(
+            &elements[0].data,
+            Some(KritorElementData::Image(x))
+                if x.file_md5.as_deref() == Some("6cd3556deb0da54bca060b4c39479839")
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_fetch_md5_max_size() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let app = axum::Router::new().route("/", axum::routing::get(|| async { vec![0u8; 2048] }));
+        tokio::spawn(
+            axum::Server::from_tcp(listener)
+                .unwrap()
+                .serve(app.into_make_service()),
+        );
+        let http = reqwest::Client::new();
+        assert_eq!(fetch_md5(&url, &http, 1024).await, None);
+        assert_eq!(
+            fetch_md5(&url, &http, 4096).await,
+            Some(format!("{:x}", md5::compute([0u8; 2048])))
+        );
+    }
+}
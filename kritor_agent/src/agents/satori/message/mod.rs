@@ -1,17 +1,111 @@
+pub mod degrade;
+pub mod dialect;
 pub mod element;
+pub mod forward;
+pub mod media;
 mod parser;
 pub use parser::Parser;
 
+use std::collections::HashMap;
+
+use kritor::common::element::ElementType;
+use kritor::common::{Contact, Scene};
+
+use super::notice::InternalNotice;
+use super::{DirectChannels, IdentityRegistry, MessageIndex, ResourceStore};
+use dialect::Dialect;
+
+/// What the element conversions need from the agent.
+pub struct Context<'a> {
+    /// where forward bundles are kept.
+    pub resources: &'a ResourceStore,
+    /// uins of platform ids.
+    pub identities: &'a IdentityRegistry,
+    /// uins of guilds, channels and roles.
+    pub guild_identities: &'a IdentityRegistry,
+    /// seqs of messages.
+    pub messages: &'a MessageIndex,
+    /// direct channels of users.
+    pub channels: &'a DirectChannels,
+    pub dialect: Dialect,
+    /// notices of `internal` events by their `_type`.
+    pub internal_notices: &'a HashMap<String, InternalNotice>,
+}
+
+/// What a `Context` borrows, owned by a test.
+#[cfg(test)]
+#[derive(Default)]
+pub(crate) struct Fixture {
+    pub resources: ResourceStore,
+    pub identities: IdentityRegistry,
+    pub guild_identities: IdentityRegistry,
+    pub messages: MessageIndex,
+    pub channels: DirectChannels,
+    pub internal_notices: HashMap<String, InternalNotice>,
+}
+
+#[cfg(test)]
+impl Fixture {
+    pub fn context(&self, dialect: Dialect) -> Context<'_> {
+        Context {
+            resources: &self.resources,
+            identities: &self.identities,
+            guild_identities: &self.guild_identities,
+            messages: &self.messages,
+            channels: &self.channels,
+            dialect,
+            internal_notices: &self.internal_notices,
+        }
+    }
+}
+
+impl Context<'_> {
+    /// The contact as kritor clients see it, with uins as peer and sub_peer.
+    ///
+    /// These are the ids `GuildService` returns and sending messages resolves,
+    /// users from the identity registry and guilds and channels from the guild one.
+    pub fn kritor_contact(&self, contact: Contact) -> Contact {
+        let peer = match Scene::try_from(contact.scene) {
+            Ok(Scene::Friend | Scene::Stranger | Scene::StrangerFromGroup) => {
+                self.identities.peer(&contact.peer)
+            }
+            _ => self.guild_identities.peer(&contact.peer),
+        };
+        Contact {
+            peer,
+            sub_peer: contact.sub_peer.map(|x| self.guild_identities.peer(&x)),
+            ..contact
+        }
+    }
+}
+
 #[derive(Debug)]
-pub struct Error(String);
+pub enum Error {
+    /// The element is malformed.
+    Invalid(String),
+    /// Satori, and the dialect, have no element for the kritor element type.
+    Unsupported(ElementType),
+}
 impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.0)
+        match self {
+            Error::Invalid(x) => write!(f, "{}", x),
+            Error::Unsupported(x) => write!(
+                f,
+                "{} elements are not supported by satori",
+                x.as_str_name().to_lowercase()
+            ),
+        }
     }
 }
 impl std::error::Error for Error {}
 impl From<&str> for Error {
     fn from(value: &str) -> Self {
-        Self(value.to_string())
+        Self::Invalid(value.to_string())
+    }
+}
+impl From<String> for Error {
+    fn from(value: String) -> Self {
+        Self::Invalid(value)
     }
 }
@@ -43,7 +43,8 @@ impl<'a> Parser<'a> {
     }
     fn parse_tag(&mut self) -> Option<()> {
         let _ = self.forward();
-        let closing = self.cur() == '/';
+        // the input may end right after `<`.
+        let closing = self.peek(0) == Some('/');
         if closing {
             let _ = self.forward();
         }
@@ -51,7 +52,8 @@ impl<'a> Parser<'a> {
         let _ = self.forward();
         if closing {
             // check tag matches
-            if self.current_element().tag().is_some_and(|x| x.name == tag) {
+            // the root element can not be closed.
+            if self.stack.len() > 1 && self.current_element().tag().is_some_and(|x| x.name == tag) {
                 self.close_element();
                 return Some(());
             } else {
@@ -89,7 +91,7 @@ impl<'a> Parser<'a> {
             .map(|x| match x.split_once('=') {
                 Some((key, value)) => (key.to_string(), {
                     let pat = &['"', '\''];
-                    if value.starts_with(pat) && value.ends_with(pat) {
+                    if value.len() >= 2 && value.starts_with(pat) && value.ends_with(pat) {
                         value[1..value.len() - 1].to_string()
                     } else {
                         value.to_string()
@@ -164,4 +166,11 @@ mod tests {
         dbg!(&ser);
         assert_eq!(ser, input);
     }
+
+    #[test]
+    fn test_malformed() {
+        for input in ["abc<", "</>", "<a>", r#"<at id="/>"#, "<<>>"] {
+            let _ = Parser::new(input).parse();
+        }
+    }
 }
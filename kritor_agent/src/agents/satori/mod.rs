@@ -1,15 +1,50 @@
+mod appender;
+mod bus;
+pub use bus::{BusEvent, EventBus, EventBusConfig, LagPolicy};
 mod client;
 pub use client::SatoriClient;
+mod dead_letter;
+pub use dead_letter::{DeadLetter, DeadLetters};
+mod direct;
+pub use direct::DirectChannels;
+mod filter;
+pub use filter::EventFilter;
+mod link;
+pub use link::LinkState;
+mod login;
+pub use login::LoginRegistry;
+mod identity;
+pub use identity::IdentityRegistry;
+mod index;
+pub use index::MessageIndex;
 mod message;
+pub use message::degrade::{DegradeConfig, Policy as DegradePolicy};
+pub use message::dialect::Dialect;
+mod notice;
+pub use notice::InternalNotice;
+mod replay;
+pub use replay::event_time;
+mod resource;
+pub use resource::ResourceStore;
+mod pipeline;
 pub mod schema;
+mod session;
+mod webhook;
+pub use webhook::WebhookConfig;
+mod websocket;
+pub use websocket::WebsocketConfig;
 
-use std::time::Duration;
+use std::sync::Arc;
 
-use futures_util::{SinkExt, StreamExt};
+use futures_util::StreamExt;
+
+use crate::proto::{agent_service_server::AgentService, *};
 #[allow(unused_imports)]
 use kritor::{
     auth::{authentication_service_server::AuthenticationService, *},
-    common::Scene,
+    common::{
+        forward_message_body::ForwardMessage, Contact, ForwardElement, PushMessageBody, Scene,
+    },
     core::{core_service_server::CoreService, *},
     developer::{developer_service_server::DeveloperService, *},
     event::{event_service_server::EventService, *},
@@ -22,17 +57,37 @@ use kritor::{
     reverse::{reverse_service_server::ReverseService, *},
     web::{web_service_server::WebService, *},
 };
-use serde_json::json;
-use tokio::{select, sync::watch::Receiver};
-use tokio_stream::wrappers::WatchStream;
-use tokio_tungstenite::tungstenite;
 use tonic::{async_trait, Response};
 
+/// How many messages `GetHistoryMessageBySeq` returns if not specified.
+const HISTORY_COUNT: u32 = 10;
+/// The most messages `GetHistoryMessageBySeq` returns at once.
+const MAX_HISTORY_COUNT: u32 = 100;
+/// How many messages `GetHistoryMessageBySeq` fetches at the same time.
+const CONCURRENT_HISTORY_FETCHES: usize = 8;
+
 type TonicServiceResult<T> = std::result::Result<tonic::Response<T>, tonic::Status>;
 
 pub struct SatoriAgent {
     pub client: SatoriClient,
-    pub events: Receiver<EventStructure>,
+    pub events: Arc<EventBus>,
+    pub resources: Arc<ResourceStore>,
+    pub identities: Arc<IdentityRegistry>,
+    /// uins of guilds, channels and roles.
+    pub guild_identities: Arc<IdentityRegistry>,
+    pub messages: Arc<MessageIndex>,
+    pub channels: Arc<DirectChannels>,
+    pub logins: Arc<LoginRegistry>,
+    pub link: Arc<LinkState>,
+    pub dead_letters: Arc<DeadLetters>,
+    /// the account selected by `SwitchAccount`.
+    pub selected: std::sync::Mutex<Option<String>>,
+    /// how to handle kritor elements satori cannot express.
+    pub degrade: DegradeConfig,
+    pub dialect: Dialect,
+    /// the most bytes of an image downloaded for its md5, if computing it is enabled.
+    pub image_md5_max_size: Option<u64>,
+    pub internal_notices: Arc<std::collections::HashMap<String, InternalNotice>>,
 }
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
 pub struct SatoriConfig {
@@ -50,94 +105,216 @@ pub struct SatoriConfig {
     /// e.g. "v1"
     pub version: String,
     pub token: Option<String>,
+    /// Platform of the login to act as, sent as `X-Platform`.
+    pub platform: Option<String>,
+    /// Id of the login to act as, sent as `X-Self-ID`.
+    ///
+    /// Without it, the first online login is used. Requests may select another
+    /// login with the `x-self-id` metadata, or switch to it by `SwitchAccount`.
+    pub self_id: Option<String>,
+    /// Per element policies for kritor elements satori cannot express.
+    ///
+    /// By default, such elements fail the whole message.
+    #[serde(default)]
+    pub degrade: DegradeConfig,
+    /// Vendor elements of the satori implementation to use, for kritor elements
+    /// without a standard satori equivalent.
+    ///
+    /// One of "standard", "chronocat", "qq" (koishi adapter-qq) or "lagrange".
+    #[serde(default)]
+    pub dialect: Dialect,
+    /// Download incoming images without an md5 to compute it, so that `file_md5`
+    /// is always set on image elements.
+    #[serde(default)]
+    pub compute_image_md5: bool,
+    /// The most bytes of an image downloaded for its md5, larger ones are left without.
+    #[serde(default = "SatoriConfig::default_image_md5_max_size")]
+    pub image_md5_max_size: u64,
+    /// File to persist the uins assigned to platform ids in.
+    ///
+    /// Without it, uins of non-numeric ids change on every restart.
+    pub identity_file: Option<std::path::PathBuf>,
+    /// File to persist the uins assigned to guild, channel and role ids in.
+    pub guild_identity_file: Option<std::path::PathBuf>,
+    /// File to persist the seqs assigned to messages in.
+    ///
+    /// Without it, seqs start over on every restart.
+    pub message_index_file: Option<std::path::PathBuf>,
+    /// How many seqs of messages are kept, besides the last one of every channel.
+    #[serde(default = "SatoriConfig::default_message_index_capacity")]
+    pub message_index_capacity: usize,
+    /// Buffering of events for subscribers.
+    #[serde(default)]
+    pub events: EventBusConfig,
+    /// How many signals that can not be turned into kritor events are kept
+    /// for `DeveloperService.GetLog`.
+    #[serde(default = "SatoriConfig::default_dead_letter_capacity")]
+    pub dead_letter_capacity: usize,
+    /// Heartbeat and reconnecting of the event websocket.
+    #[serde(default)]
+    pub websocket: WebsocketConfig,
+    /// Receive events from satori webhooks, besides or instead of the event websocket.
+    pub webhook: Option<WebhookConfig>,
+    /// Notices to deliver `internal` events of the satori implementation as,
+    /// by their `_type`, on top of the known QQ ones.
+    ///
+    /// One of "poke", "file_uploaded", "essence_changed" or "title_changed".
+    #[serde(default)]
+    pub internal_events: std::collections::HashMap<String, InternalNotice>,
+}
+impl SatoriConfig {
+    fn default_dead_letter_capacity() -> usize {
+        256
+    }
+    fn default_image_md5_max_size() -> u64 {
+        16 * 1024 * 1024
+    }
+    fn default_message_index_capacity() -> usize {
+        MessageIndex::DEFAULT_CAPACITY
+    }
 }
 impl SatoriAgent {
-    pub fn new(base_url: reqwest::Url, token: Option<String>) -> Self {
-        let (tx, rx) = tokio::sync::watch::channel(Default::default());
-        let base_url_clone = base_url.clone();
-        let token_clone = token.clone();
-        tokio::spawn(async move {
-            let mut base_url = base_url_clone;
-            let token = token_clone;
-            base_url.set_scheme("ws").unwrap();
-            let ws_endpoint = base_url.join("events").unwrap();
-            loop {
-                log::info!(
-                    "try connecting satori event websocket on {}...",
-                    ws_endpoint
-                );
-                let conn = tokio_tungstenite::connect_async(&ws_endpoint).await;
-                if let Ok((mut ws, _)) = conn {
-                    log::info!("Connected to satori server for event websocket");
-                    if let Some(token) = &token {
-                        let _ = ws
-                            .send(tungstenite::Message::text(format!(
-                                r#"{{"op":3,"body":{{"token":"{}"}}}}"#,
-                                token
-                            )))
-                            .await;
-                    }
-                    let mut interval = tokio::time::interval(Duration::from_secs(10));
-                    loop {
-                        select! {
-                            _ = interval.tick() => {
-                               let r = ws.send(tungstenite::Message::text(r#"{"op":1}"#)).await;
-                               if r.is_err() {
-                                   log::error!("Failed to send ping to satori server for event websocket/connection closed?...reconnecting...");
-                                   break;
-                               }
-                            },
-                            next = ws.next() => {
-                                if let Some(Ok(msg)) = next {
-                                    log::debug!("Received message from satori server for event websocket: {:?}", msg);
-                                    if let tungstenite::Message::Text(s) = msg{
-                                        let mut data:serde_json::Value = serde_json::from_str(s.as_str()).unwrap();
-                                        if !data.get("op").is_some_and(|x|x==&json!(0)) {
-                                            continue;
-                                        }
-                                        if let Some(Ok(ev)) =data.get_mut("body")
-                                            .map(|x|serde_json::from_value::<schema::Event>(x.take())
-                                            .map_err(|_|())
-                                            .and_then(TryInto::try_into)) {
-                                            log::debug!("received message event:{:?}",ev);
-                                            let _ = tx.send(ev);
-                                        }
-                                    }
-                                    //    if let Ok(ev) = serde_json::from_str(msg.to_text().unwrap()) {
-                                    //        let _ = tx.send(ev);
-                                    //    }
-                                } else {
-                                    log::error!("Failed to receive message from satori server for event websocket/connection closed?...reconnecting...");
-                                    break;
-                                }
-                            }
-                        }
-                    }
-                } else {
-                    log::error!("Failed to connect to satori server for event websocket:{}...retrying in 5 seconds",conn.unwrap_err());
-                    tokio::time::sleep(std::time::Duration::from_secs(5)).await;
-                }
-            }
+    pub fn new(base_url: reqwest::Url, opts: SatoriConfig) -> std::io::Result<Self> {
+        let events = Arc::new(EventBus::new(&opts.events)?);
+        let resources = Arc::new(ResourceStore::default());
+        let identities = Arc::new(match &opts.identity_file {
+            Some(path) => IdentityRegistry::open(path)?,
+            None => IdentityRegistry::default(),
+        });
+        let guild_identities = Arc::new(match &opts.guild_identity_file {
+            Some(path) => IdentityRegistry::open(path)?,
+            None => IdentityRegistry::default(),
+        });
+        let messages = Arc::new(match &opts.message_index_file {
+            Some(path) => MessageIndex::open(path, opts.message_index_capacity)?,
+            None => MessageIndex::new(opts.message_index_capacity),
+        });
+        let token = opts.token;
+        let dialect = opts.dialect;
+        let http = reqwest::Client::new();
+        let channels = Arc::new(DirectChannels::default());
+        let logins = Arc::new(LoginRegistry::default());
+        let link = Arc::new(LinkState::default());
+        let dead_letters = Arc::new(DeadLetters::new(opts.dead_letter_capacity));
+        let internal_notices = Arc::new(notice::internal_notices(&opts.internal_events));
+        let pipeline = Arc::new(pipeline::Pipeline {
+            events: events.clone(),
+            resources: resources.clone(),
+            identities: identities.clone(),
+            guild_identities: guild_identities.clone(),
+            messages: messages.clone(),
+            channels: channels.clone(),
+            logins: logins.clone(),
+            dialect,
+            image_md5: opts
+                .compute_image_md5
+                .then(|| pipeline::Outbox::new(events.clone())),
+            image_md5_max_size: opts.image_md5_max_size,
+            http: http.clone(),
+            session: Default::default(),
+            dead_letters: dead_letters.clone(),
+            internal_notices: internal_notices.clone(),
         });
-        Self {
+        if let Some(webhook) = opts.webhook.clone() {
+            let webhook_token = webhook.token(token.as_deref())?;
+            let listener = webhook::bind(&webhook)?;
+            tokio::spawn(webhook::serve(
+                listener,
+                webhook,
+                webhook_token,
+                pipeline.clone(),
+            ));
+        }
+        if !matches!(
+            opts.webhook,
+            Some(WebhookConfig {
+                websocket: false,
+                ..
+            })
+        ) {
+            tokio::spawn(websocket::run(
+                base_url.clone(),
+                token.clone(),
+                opts.websocket.clone(),
+                link.clone(),
+                pipeline,
+            ));
+        }
+        Ok(Self {
             client: SatoriClient {
-                client: reqwest::Client::new(),
+                client: http,
                 base_url,
                 token,
+                platform: opts.platform,
+                self_id: opts.self_id,
             },
-            events: rx,
-        }
+            events,
+            resources,
+            identities,
+            guild_identities,
+            messages,
+            channels,
+            logins,
+            link,
+            dead_letters,
+            selected: Default::default(),
+            degrade: opts.degrade,
+            image_md5_max_size: opts.compute_image_md5.then_some(opts.image_md5_max_size),
+            dialect,
+            internal_notices,
+        })
     }
-    pub fn try_from_opts(opts: SatoriConfig) -> Result<Self, url::ParseError> {
+    pub fn try_from_opts(opts: SatoriConfig) -> anyhow::Result<Self> {
         let base_url = reqwest::Url::parse(&format!(
             "{}://{}:{}/{}{}/",
             opts.scheme,
             opts.host,
             opts.port,
-            opts.path.unwrap_or_default(),
+            opts.path.as_deref().unwrap_or_default(),
             opts.version
         ))?;
-        Ok(Self::new(base_url, opts.token))
+        Ok(Self::new(base_url, opts)?)
+    }
+    /// The login a request acts as.
+    ///
+    /// It is selected by the `x-self-id` metadata, by `SwitchAccount` or by the
+    /// config, in that order, and otherwise is the current login. A selected
+    /// login the satori server does not have fails with its account, rather
+    /// than acting as another account.
+    fn login(
+        &self,
+        metadata: &tonic::metadata::MetadataMap,
+    ) -> Result<Option<schema::Login>, String> {
+        let self_id = metadata
+            .get("x-self-id")
+            .and_then(|x| x.to_str().ok())
+            .map(ToString::to_string)
+            .or(self.selected.lock().unwrap().clone())
+            .or(self.client.self_id.clone());
+        let platform = metadata
+            .get("x-platform")
+            .and_then(|x| x.to_str().ok())
+            .map(ToString::to_string)
+            .or(self.client.platform.clone());
+        self.logins.select(self_id, platform)
+    }
+    /// A client acting as the login of a request.
+    fn client(&self, metadata: &tonic::metadata::MetadataMap) -> Result<SatoriClient, String> {
+        Ok(match self.login(metadata)? {
+            Some(login) => self.client.with_login(&login),
+            None => self.client.clone(),
+        })
+    }
+    fn context(&self) -> message::Context<'_> {
+        message::Context {
+            resources: &self.resources,
+            identities: &self.identities,
+            guild_identities: &self.guild_identities,
+            messages: &self.messages,
+            channels: &self.channels,
+            dialect: self.dialect,
+            internal_notices: &self.internal_notices,
+        }
     }
 }
 
@@ -155,72 +332,439 @@ impl CoreService for SatoriAgent {
     }
     async fn get_current_account(
         &self,
-        _request: tonic::Request<GetCurrentAccountRequest>,
+        request: tonic::Request<GetCurrentAccountRequest>,
     ) -> TonicServiceResult<GetCurrentAccountResponse> {
-        self.client
-            .login_get()
-            .await
-            .map(|login| {
-                Response::new({
-                    if let Some(user) = login.user {
-                        GetCurrentAccountResponse {
-                            account_name: user.name.unwrap_or_default(),
-                            account_uin: user.id.parse().unwrap_or_default(),
-                            account_uid: None,
-                        }
-                    } else {
-                        let id = login.self_id.unwrap_or_default();
-                        GetCurrentAccountResponse {
-                            account_name: "".into(),
-                            account_uin: id.parse().unwrap_or_default(),
-                            account_uid: None,
-                        }
-                    }
-                })
-            })
-            .map_err(Into::into)
+        let login = self
+            .login(request.metadata())
+            .map_err(no_login)?
+            .ok_or(tonic::Status::unavailable("no login ready"))?;
+        Ok(Response::new({
+            if let Some(user) = login.user {
+                GetCurrentAccountResponse {
+                    account_name: user.name.unwrap_or_default(),
+                    account_uin: self.identities.uin(&user.id),
+                    account_uid: Some(user.id),
+                }
+            } else {
+                let id = login.self_id.unwrap_or_default();
+                GetCurrentAccountResponse {
+                    account_name: "".into(),
+                    account_uin: self.identities.uin(&id),
+                    account_uid: Some(id),
+                }
+            }
+        }))
+    }
+    /// Select the login later requests act as, among the logins of the satori server.
+    async fn switch_account(
+        &self,
+        request: tonic::Request<SwitchAccountRequest>,
+    ) -> TonicServiceResult<SwitchAccountResponse> {
+        let self_id = match request.into_inner().account {
+            Some(switch_account_request::Account::AccountUid(uid)) => uid,
+            Some(switch_account_request::Account::AccountUin(uin)) => {
+                self.identities.platform_id(None, uin)
+            }
+            None => return Err(tonic::Status::invalid_argument("account")),
+        };
+        if !self.logins.is_empty() && self.logins.find(&self_id).is_none() {
+            return Err(tonic::Status::not_found("no such login"));
+        }
+        log::info!("Switched to account {}", self_id);
+        *self.selected.lock().unwrap() = Some(self_id);
+        Ok(Response::new(SwitchAccountResponse {}))
+    }
+}
+#[async_trait]
+impl DeveloperService for SatoriAgent {
+    /// The dead letters from the id `start` on, one json object per line.
+    ///
+    /// With `recent`, only the latest one is returned.
+    async fn get_log(
+        &self,
+        request: tonic::Request<GetLogRequest>,
+    ) -> TonicServiceResult<GetLogResponse> {
+        let request = request.into_inner();
+        let mut letters = self.dead_letters.since(request.start);
+        if request.recent.unwrap_or_default() {
+            letters = letters.pop().into_iter().collect();
+        }
+        let log = letters
+            .iter()
+            .filter_map(|x| serde_json::to_string(x).ok())
+            .collect::<Vec<_>>()
+            .join("\n");
+        Ok(Response::new(GetLogResponse {
+            is_success: true,
+            log,
+        }))
     }
 }
-impl DeveloperService for SatoriAgent {}
 #[async_trait]
 impl EventService for SatoriAgent {
     async fn register_active_listener(
         &self,
-        _request: tonic::Request<RequestPushEvent>,
+        request: tonic::Request<RequestPushEvent>,
     ) -> TonicServiceResult<tonic::codegen::BoxStream<EventStructure>> {
-        let mut r = self.events.clone();
-        r.mark_unchanged();
-        Ok(Response::new(Box::pin(
-            WatchStream::from_changes(r).map(Ok),
-        )))
+        let filter = EventFilter::from_request(
+            request.get_ref(),
+            request.metadata(),
+            &self.identities,
+            &self.guild_identities,
+        );
+        Ok(Response::new(self.events.subscribe(filter)))
     }
 }
 
 impl GroupFileService for SatoriAgent {}
-impl FriendService for SatoriAgent {}
+#[async_trait]
+impl FriendService for SatoriAgent {
+    async fn get_uid_by_uin(
+        &self,
+        request: tonic::Request<GetUidByUinRequest>,
+    ) -> TonicServiceResult<GetUidByUinResponse> {
+        Ok(Response::new(GetUidByUinResponse {
+            uid_map: request
+                .into_inner()
+                .target_uins
+                .into_iter()
+                .filter_map(|uin| Some((uin, self.identities.uid(uin)?)))
+                .collect(),
+        }))
+    }
+    async fn get_uin_by_uid(
+        &self,
+        request: tonic::Request<GetUinByUidRequest>,
+    ) -> TonicServiceResult<GetUinByUidResponse> {
+        Ok(Response::new(GetUinByUidResponse {
+            uin_map: request
+                .into_inner()
+                .target_uids
+                .into_iter()
+                .map(|uid| (uid.clone(), self.identities.uin(&uid)))
+                .collect(),
+        }))
+    }
+}
 impl GroupService for SatoriAgent {}
-impl GuildService for SatoriAgent {}
+/// Guilds, channels and roles are identified by uins from the guild identity
+/// registry, as kritor expects numeric ids for them, and members by the uins of users.
+#[async_trait]
+impl GuildService for SatoriAgent {
+    async fn get_bot_info(
+        &self,
+        request: tonic::Request<GetBotInfoRequest>,
+    ) -> TonicServiceResult<GetBotInfoResponse> {
+        let user = self
+            .login(request.metadata())
+            .map_err(no_login)?
+            .ok_or(tonic::Status::unavailable("no login ready"))?
+            .user
+            .ok_or(tonic::Status::unavailable("satori returned no user"))?;
+        Ok(Response::new(GetBotInfoResponse {
+            nickname: user.nick.or(user.name).unwrap_or_default(),
+            tiny_id: self.identities.uin(&user.id),
+            avatar: user.avatar.unwrap_or_default(),
+        }))
+    }
+    async fn get_channel_list(
+        &self,
+        request: tonic::Request<GetChannelListRequest>,
+    ) -> TonicServiceResult<GetChannelListResponse> {
+        let client = self.client(request.metadata()).map_err(no_login)?;
+        let mut guilds = Vec::new();
+        let mut next = None;
+        loop {
+            let list = client.guild_list(next).await?;
+            guilds.extend(list.data.into_iter().map(|x| GuildInfo {
+                guild_id: self.guild_identities.uin(&x.id),
+                guild_name: x.name.unwrap_or_default(),
+                guild_display_id: x.id,
+                ..Default::default()
+            }));
+            next = list.next;
+            if next.is_none() {
+                break;
+            }
+        }
+        Ok(Response::new(GetChannelListResponse {
+            get_channel_list: guilds,
+        }))
+    }
+    async fn get_guild_meta_by_guest(
+        &self,
+        request: tonic::Request<GetGuildMetaByGuestRequest>,
+    ) -> TonicServiceResult<GetGuildMetaByGuestResponse> {
+        let client = self.client(request.metadata()).map_err(no_login)?;
+        let guild_id = request.into_inner().guild_id;
+        let guild = client
+            .guild_get(self.guild_identities.platform_id(None, guild_id))
+            .await?;
+        Ok(Response::new(GetGuildMetaByGuestResponse {
+            guild_id,
+            guild_name: guild.name.unwrap_or_default(),
+            guild_display_id: guild.id,
+            ..Default::default()
+        }))
+    }
+    async fn get_guild_channel_list(
+        &self,
+        request: tonic::Request<GetGuildChannelListRequest>,
+    ) -> TonicServiceResult<GetGuildChannelListResponse> {
+        let client = self.client(request.metadata()).map_err(no_login)?;
+        let guild_id = request.into_inner().guild_id;
+        let platform_guild_id = self.guild_identities.platform_id(None, guild_id);
+        let mut channels = Vec::new();
+        let mut next = None;
+        loop {
+            let list = client.channel_list(platform_guild_id.clone(), next).await?;
+            channels.extend(list.data.into_iter().map(|x| {
+                ChannelInfo {
+                    channel_id: self.guild_identities.uin(&x.id),
+                    guild_id,
+                    channel_name: x.name.unwrap_or_default(),
+                    category_id: x
+                        .parent_id
+                        .map(|x| self.guild_identities.uin(&x))
+                        .unwrap_or_default(),
+                    ..Default::default()
+                }
+            }));
+            next = list.next;
+            if next.is_none() {
+                break;
+            }
+        }
+        Ok(Response::new(GetGuildChannelListResponse {
+            channels_info: channels,
+        }))
+    }
+    async fn get_guild_member_list(
+        &self,
+        request: tonic::Request<GetGuildMemberListRequest>,
+    ) -> TonicServiceResult<GetGuildMemberListResponse> {
+        let client = self.client(request.metadata()).map_err(no_login)?;
+        let request = request.into_inner();
+        let guild_id = self.guild_identities.platform_id(None, request.guild_id);
+        let mut members = Vec::new();
+        let mut next = (!request.next_token.is_empty()).then_some(request.next_token);
+        loop {
+            let list = client.guild_member_list(guild_id.clone(), next).await?;
+            // members without a user can not be referred to.
+            members.extend(list.data.into_iter().filter_map(|x| {
+                let user = x.user?;
+                Some(MemberInfo {
+                    tiny_id: self.identities.uin(&user.id),
+                    nickname: x.nick.or(user.nick).or(user.name).unwrap_or_default(),
+                    join_time: x.joined_at.unwrap_or_default() as u64 / 1000,
+                    robot_type: user.is_bot.unwrap_or_default() as u64,
+                    ..Default::default()
+                })
+            }));
+            next = list.next;
+            if next.is_none() || !request.all {
+                break;
+            }
+        }
+        Ok(Response::new(GetGuildMemberListResponse {
+            members_info: members,
+            finished: next.is_none(),
+            next_token: next.unwrap_or_default(),
+        }))
+    }
+    async fn get_guild_member(
+        &self,
+        request: tonic::Request<GetGuildMemberRequest>,
+    ) -> TonicServiceResult<GetGuildMemberResponse> {
+        let client = self.client(request.metadata()).map_err(no_login)?;
+        let request = request.into_inner();
+        let member = client
+            .guild_member_get(
+                self.guild_identities.platform_id(None, request.guild_id),
+                self.identities.platform_id(None, request.tiny_id),
+            )
+            .await?;
+        let user = member.user;
+        Ok(Response::new(GetGuildMemberResponse {
+            member_info: Some(MemberProfile {
+                tiny_id: request.tiny_id,
+                nickname: member
+                    .nick
+                    .or(user.as_ref().and_then(|x| x.nick.clone()))
+                    .or(user.as_ref().and_then(|x| x.name.clone()))
+                    .unwrap_or_default(),
+                avatar_url: member
+                    .avatar
+                    .or(user.and_then(|x| x.avatar))
+                    .unwrap_or_default(),
+                join_time: member.joined_at.unwrap_or_default() as u64 / 1000,
+                ..Default::default()
+            }),
+        }))
+    }
+    async fn get_guild_role_list(
+        &self,
+        request: tonic::Request<GetGuildRoleListRequest>,
+    ) -> TonicServiceResult<GetGuildRoleListResponse> {
+        let client = self.client(request.metadata()).map_err(no_login)?;
+        let guild_id = self
+            .guild_identities
+            .platform_id(None, request.into_inner().guild_id);
+        let mut roles = Vec::new();
+        let mut next = None;
+        loop {
+            let list = client.guild_role_list(guild_id.clone(), next).await?;
+            roles.extend(list.data.into_iter().map(|x| RoleInfo {
+                role_id: self.guild_identities.uin(&x.id),
+                role_name: x.name.unwrap_or_default(),
+                ..Default::default()
+            }));
+            next = list.next;
+            if next.is_none() {
+                break;
+            }
+        }
+        Ok(Response::new(GetGuildRoleListResponse {
+            roles_info: roles,
+        }))
+    }
+}
+impl SatoriAgent {
+    /// Figure out the satori channel to send messages to for a kritor contact.
+    async fn channel_id(
+        &self,
+        client: &SatoriClient,
+        contact: &Contact,
+    ) -> Result<String, tonic::Status> {
+        match contact.scene.try_into().ok() {
+            // the channel is the sub_peer, which may be the id from `GetGuildChannelList`.
+            Some(Scene::Group | Scene::Guild) => Ok(self
+                .guild_identities
+                .resolve_peer(contact.sub_peer.clone().unwrap_or(contact.peer.clone()))),
+            Some(Scene::Friend) => self.direct_channel(client, &contact.peer, None).await,
+            // temporary sessions, which some platforms open through the guild.
+            Some(Scene::Stranger | Scene::StrangerFromGroup) => {
+                let guild_id = contact
+                    .sub_peer
+                    .clone()
+                    .map(|x| self.guild_identities.resolve_peer(x));
+                self.direct_channel(client, &contact.peer, guild_id).await
+            }
+            _ => Err(tonic::Status::invalid_argument(
+                "The scene is not supported by satori",
+            )),
+        }
+    }
+    /// The direct channel with a user, opened with `user.channel.create` if not known yet.
+    ///
+    /// Implementations without `user.channel.create` can only reply to users
+    /// whose direct messages have been received.
+    async fn direct_channel(
+        &self,
+        client: &SatoriClient,
+        peer: &str,
+        guild_id: Option<String>,
+    ) -> Result<String, tonic::Status> {
+        let user_id = self.identities.resolve_peer(peer.to_string());
+        let self_id = client.self_id.clone().unwrap_or_default();
+        if let Some(channel_id) = self.channels.get(&self_id, &user_id, guild_id.as_deref()) {
+            return Ok(channel_id);
+        }
+        let channel = client
+            .user_channel_create(user_id.clone(), guild_id.clone())
+            .await
+            .map_err(|e| match e {
+                // a missing user is NOT_FOUND like other resources.
+                client::Error::MethodNotAllowed => tonic::Status::unimplemented(
+                    "satori does not support user.channel.create, \
+                        and no direct message from the user has been received",
+                ),
+                e => e.into(),
+            })?;
+        self.channels
+            .insert(self_id, user_id, guild_id, channel.id.clone());
+        Ok(channel.id)
+    }
+    /// Create a message and return the last one satori created for it.
+    ///
+    /// All the created messages are given a seq.
+    async fn create_message(
+        &self,
+        client: &SatoriClient,
+        channel_id: String,
+        content: String,
+    ) -> Result<schema::Message, tonic::Status> {
+        let mut resp = client
+            .message_create(channel_id.clone(), content)
+            .await
+            .map_err(|e| tonic::Status::internal(format!("satori returned a error for {}", e)))?;
+        for message in &resp {
+            self.messages.seq(&channel_id, &message.id);
+        }
+        resp.pop().ok_or(tonic::Status::internal(
+            "satori returned a empty message list",
+        ))
+    }
+    /// Get a message by id and convert it into a kritor one.
+    async fn get_message(
+        &self,
+        client: &SatoriClient,
+        channel_id: String,
+        contact: &Contact,
+        message_id: String,
+    ) -> Result<PushMessageBody, tonic::Status> {
+        let (channel_type, guild_id) = match contact.scene.try_into().ok() {
+            Some(Scene::Friend | Scene::Stranger) => (schema::ChannelType::DIRECT, None),
+            Some(Scene::StrangerFromGroup) => (
+                schema::ChannelType::DIRECT,
+                contact
+                    .sub_peer
+                    .clone()
+                    .map(|x| self.guild_identities.resolve_peer(x)),
+            ),
+            Some(Scene::Guild) => (
+                schema::ChannelType::TEXT,
+                Some(self.guild_identities.resolve_peer(contact.peer.clone())),
+            ),
+            _ => (schema::ChannelType::TEXT, None),
+        };
+        let mut body = client
+            .message_get(channel_id.clone(), message_id)
+            .await?
+            .try_into_kritor(channel_id, channel_type, guild_id, &self.context())
+            .map_err(|e| {
+                tonic::Status::internal(format!("Failed to convert the satori message: {}", e))
+            })?;
+        if let Some(max_size) = self.image_md5_max_size {
+            message::media::fill_image_md5(&mut body.elements, &client.client, max_size).await;
+        }
+        Ok(body)
+    }
+}
+
 #[async_trait]
 impl MessageService for SatoriAgent {
     async fn send_message(
         &self,
         request: tonic::Request<SendMessageRequest>,
     ) -> std::result::Result<tonic::Response<SendMessageResponse>, tonic::Status> {
+        let client = self.client(request.metadata()).map_err(no_login)?;
         let mut request = request.into_inner();
         let contact = request
             .contact
             .take()
             .ok_or(tonic::Status::invalid_argument("contact"))?;
-        let channel_id = match contact.scene.try_into().ok() {
-            Some(Scene::Group) => contact.peer,
-            Some(Scene::Friend) => format!("private:{}", contact.peer),
-            _ => {
-                return Err(tonic::Status::invalid_argument(
-                    "The scene is not supported by satori",
+        let channel_id = self.channel_id(&client, &contact).await?;
+        let (elements, degraded) = self
+            .degrade
+            .apply(request.elements, None, &self.context())
+            .map_err(|e| {
+                tonic::Status::invalid_argument(format!(
+                    "Failed to convert kritor elements to satori elements:{}",
+                    e
                 ))
-            }
-        };
-        let content = message::element::Root::try_from_kritor_elements(request.elements)
+            })?;
+        let content = message::element::Root::try_from_kritor_elements(elements, &self.context())
             .map(|x| x.root_element.serialize())
             .map_err(|e| {
                 tonic::Status::invalid_argument(format!(
@@ -228,19 +772,221 @@ impl MessageService for SatoriAgent {
                     e
                 ))
             })?;
-        let mut resp = self
-            .client
-            .message_create(channel_id, content)
-            .await
-            .map_err(|e| tonic::Status::internal(format!("satori returned a error for {}", e)))?;
-        let last = resp.pop().ok_or(tonic::Status::internal(
-            "satori returned a empty message list",
-        ))?;
-        Ok(Response::new(SendMessageResponse {
+        let last = self.create_message(&client, channel_id, content).await?;
+        let mut resp = Response::new(SendMessageResponse {
+            message_id: last.id,
+            message_time: last.created_at.unwrap_or_default() as _,
+        });
+        set_degraded_metadata(&mut resp, degraded);
+        Ok(resp)
+    }
+    async fn send_message_by_res_id(
+        &self,
+        request: tonic::Request<SendMessageByResIdRequest>,
+    ) -> TonicServiceResult<SendMessageByResIdResponse> {
+        let client = self.client(request.metadata()).map_err(no_login)?;
+        let mut request = request.into_inner();
+        let contact = request
+            .contact
+            .take()
+            .ok_or(tonic::Status::invalid_argument("contact"))?;
+        let channel_id = self.channel_id(&client, &contact).await?;
+        let content = message::forward::from_kritor_forward(
+            ForwardElement {
+                res_id: request.res_id,
+                ..Default::default()
+            },
+            &self.context(),
+        )
+        .map(|x| x.serialize())
+        .map_err(|e| {
+            tonic::Status::invalid_argument(format!(
+                "Failed to convert forward message to satori elements:{}",
+                e
+            ))
+        })?;
+        let last = self.create_message(&client, channel_id, content).await?;
+        Ok(Response::new(SendMessageByResIdResponse {
             message_id: last.id,
             message_time: last.created_at.unwrap_or_default() as _,
         }))
     }
+    async fn get_message_by_seq(
+        &self,
+        request: tonic::Request<GetMessageBySeqRequest>,
+    ) -> TonicServiceResult<GetMessageBySeqResponse> {
+        let client = self.client(request.metadata()).map_err(no_login)?;
+        let request = request.into_inner();
+        let contact = request
+            .contact
+            .ok_or(tonic::Status::invalid_argument("contact"))?;
+        let channel_id = self.channel_id(&client, &contact).await?;
+        let message_id = self
+            .messages
+            .message_id(&channel_id, request.message_seq)
+            .ok_or(tonic::Status::not_found("message not found"))?;
+        Ok(Response::new(GetMessageBySeqResponse {
+            message: Some(
+                self.get_message(&client, channel_id, &contact, message_id)
+                    .await?,
+            ),
+        }))
+    }
+    async fn get_history_message_by_seq(
+        &self,
+        request: tonic::Request<GetHistoryMessageBySeqRequest>,
+    ) -> TonicServiceResult<GetHistoryMessageBySeqResponse> {
+        let client = self.client(request.metadata()).map_err(no_login)?;
+        let request = request.into_inner();
+        let contact = request
+            .contact
+            .ok_or(tonic::Status::invalid_argument("contact"))?;
+        let channel_id = self.channel_id(&client, &contact).await?;
+        let history = self.messages.history(
+            &channel_id,
+            request.start_message_seq,
+            request
+                .count
+                .unwrap_or(HISTORY_COUNT)
+                .min(MAX_HISTORY_COUNT) as usize,
+        );
+        let (client, channel_id, contact) = (&client, &channel_id, &contact);
+        let messages = futures_util::stream::iter(history)
+            .map(|(seq, message_id)| async move {
+                // messages deleted or no longer visible are left out.
+                self.get_message(client, channel_id.clone(), contact, message_id)
+                    .await
+                    .map_err(|e| {
+                        log::warn!("Failed to get message {} of {}: {}", seq, channel_id, e)
+                    })
+                    .ok()
+            })
+            .buffered(CONCURRENT_HISTORY_FETCHES)
+            .filter_map(futures_util::future::ready)
+            .collect()
+            .await;
+        Ok(Response::new(GetHistoryMessageBySeqResponse { messages }))
+    }
+    async fn upload_forward_message(
+        &self,
+        request: tonic::Request<UploadForwardMessageRequest>,
+    ) -> TonicServiceResult<UploadForwardMessageResponse> {
+        let mut request = request.into_inner();
+        if request.messages.is_empty() {
+            return Err(tonic::Status::invalid_argument("messages"));
+        }
+        let map_err = |e: message::Error| {
+            tonic::Status::invalid_argument(format!(
+                "Failed to convert forward message to satori elements:{}",
+                e
+            ))
+        };
+        let mut degraded = Vec::new();
+        for (index, message) in request.messages.iter_mut().enumerate() {
+            if let Some(ForwardMessage::Message(message)) = &mut message.forward_message {
+                let elements = std::mem::take(&mut message.elements);
+                let (elements, d) = self
+                    .degrade
+                    .apply(elements, Some(index), &self.context())
+                    .map_err(map_err)?;
+                message.elements = elements;
+                degraded.extend(d);
+            }
+        }
+        // make sure the bundle can be sent later before accepting it.
+        message::forward::to_forward_element(request.messages.clone(), &self.context())
+            .map_err(map_err)?;
+        // satori has no api to store forward messages, so the bundle is kept by the agent.
+        let res_id = self.resources.put_forward(request.messages);
+        let mut resp = Response::new(UploadForwardMessageResponse { res_id });
+        set_degraded_metadata(&mut resp, degraded);
+        Ok(resp)
+    }
+    async fn download_forward_message(
+        &self,
+        request: tonic::Request<DownloadForwardMessageRequest>,
+    ) -> TonicServiceResult<DownloadForwardMessageResponse> {
+        let request = request.into_inner();
+        let bundle = self
+            .resources
+            .get_forward(&request.res_id)
+            .ok_or(tonic::Status::not_found("forward message not found"))?;
+        Ok(Response::new(DownloadForwardMessageResponse {
+            messages: bundle
+                .into_iter()
+                .filter_map(|x| x.forward_message)
+                .map(|x| match x {
+                    ForwardMessage::Message(message) => message,
+                    ForwardMessage::MessageId(message_id) => PushMessageBody {
+                        message_id,
+                        ..Default::default()
+                    },
+                })
+                .collect(),
+        }))
+    }
+}
+
+/// The error of a request selecting an account the satori server has no login of.
+fn no_login(self_id: String) -> tonic::Status {
+    tonic::Status::not_found(format!("no login of account {}", self_id))
+}
+
+/// Tell the caller which elements have been degraded, in the `x-degraded-elements` metadata.
+///
+/// The value is a comma separated list of `{index}:{type}:{policy}`, the index
+/// being `{message}.{element}` for forward bundles.
+fn set_degraded_metadata<T>(resp: &mut Response<T>, degraded: Vec<message::degrade::Degraded>) {
+    if degraded.is_empty() {
+        return;
+    }
+    let value = degraded
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(",");
+    if let Ok(value) = value.parse() {
+        resp.metadata_mut().insert("x-degraded-elements", value);
+    }
+}
+
+#[async_trait]
+impl AgentService for SatoriAgent {
+    async fn get_backend_status(
+        &self,
+        _request: tonic::Request<GetBackendStatusRequest>,
+    ) -> TonicServiceResult<GetBackendStatusResponse> {
+        Ok(Response::new(GetBackendStatusResponse {
+            is_connected: self.link.is_connected(),
+            last_connected_time: self.link.last_connected(),
+            reconnect_count: self.link.reconnects(),
+            failed_event_count: self.dead_letters.failed(),
+            unsupported_event_count: self.dead_letters.unsupported(),
+            dropped_event_count: self.events.dropped(),
+        }))
+    }
+    type SubscribeEventsStream = tonic::codegen::BoxStream<SubscribedEvent>;
+    async fn subscribe_events(
+        &self,
+        request: tonic::Request<SubscribeEventsRequest>,
+    ) -> TonicServiceResult<Self::SubscribeEventsStream> {
+        let mut filter = EventFilter::from_request(
+            &RequestPushEvent {
+                r#type: request.get_ref().r#type,
+            },
+            request.metadata(),
+            &self.identities,
+            &self.guild_identities,
+        );
+        filter.resume_after = request.get_ref().resume_after;
+        Ok(Response::new(self.events.subscribe_with(
+            filter,
+            |id, event| SubscribedEvent {
+                id,
+                event: Some(event),
+            },
+        )))
+    }
 }
 
 impl ProcessService for SatoriAgent {}
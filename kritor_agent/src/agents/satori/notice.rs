@@ -0,0 +1,462 @@
+use std::collections::HashMap;
+
+use kritor::event::{
+    event_structure, group_member_decreased_notice::GroupMemberDecreasedType,
+    group_member_increased_notice::GroupMemberIncreasedType, notice_event, EventStructure,
+    EventType, FriendDecreasedNotice, FriendIncreasedNotice, GroupEssenceMessageNotice,
+    GroupFileUploadedNotice, GroupMemberDecreasedNotice, GroupMemberIncreasedNotice,
+    GroupPokeNotice, GroupUniqueTitleChangedNotice, NoticeEvent, PrivateFileUploadedNotice,
+    PrivatePokeNotice,
+};
+use serde_json::Value;
+
+use super::message::Context;
+use super::schema::{ConversionError, Event};
+
+/// Satori event types delivered as kritor notices.
+pub const NOTICE_TYPES: &[&str] = &[
+    "guild-added",
+    "guild-removed",
+    "friend-added",
+    "friend-removed",
+];
+
+/// The kritor notice an `internal` event is delivered as.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InternalNotice {
+    /// group or private poke, by whether there is a group.
+    Poke,
+    /// group or private file uploaded, by whether there is a group.
+    FileUploaded,
+    /// essence message set or removed.
+    EssenceChanged,
+    /// special title of a group member changed.
+    TitleChanged,
+}
+
+/// Notices of the `internal` events by their `_type`, the ones of QQ
+/// implementations followed by the configured ones.
+pub fn internal_notices(
+    configured: &HashMap<String, InternalNotice>,
+) -> HashMap<String, InternalNotice> {
+    let mut notices: HashMap<_, _> = [
+        ("poke", InternalNotice::Poke),
+        ("notify/poke", InternalNotice::Poke),
+        ("group_upload", InternalNotice::FileUploaded),
+        ("offline_file", InternalNotice::FileUploaded),
+        ("essence", InternalNotice::EssenceChanged),
+        ("title", InternalNotice::TitleChanged),
+        ("notify/title", InternalNotice::TitleChanged),
+    ]
+    .into_iter()
+    .map(|(k, v)| (k.to_string(), v))
+    .collect();
+    notices.extend(configured.iter().map(|(k, v)| (k.clone(), *v)));
+    notices
+}
+
+/// An id in the data, which may be either a string or a number.
+fn data_id(data: &Value, key: &str) -> Option<String> {
+    match data.get(key)? {
+        Value::String(x) => Some(x.clone()),
+        Value::Number(x) => Some(x.to_string()),
+        _ => None,
+    }
+}
+
+fn data_str(data: &Value, key: &str) -> String {
+    data.get(key)
+        .and_then(|x| x.as_str())
+        .unwrap_or_default()
+        .to_string()
+}
+
+fn notice(time: u64, notice_id: String, notice: notice_event::Notice) -> EventStructure {
+    let r#type = match &notice {
+        notice_event::Notice::GroupMemberIncrease(_) => {
+            notice_event::NoticeType::GroupMemberIncrease
+        }
+        notice_event::Notice::GroupMemberDecrease(_) => {
+            notice_event::NoticeType::GroupMemberDecrease
+        }
+        notice_event::Notice::PrivatePoke(_) => notice_event::NoticeType::PrivatePoke,
+        notice_event::Notice::GroupPoke(_) => notice_event::NoticeType::GroupPoke,
+        notice_event::Notice::PrivateFileUploaded(_) => {
+            notice_event::NoticeType::PrivateFileUploaded
+        }
+        notice_event::Notice::GroupFileUploaded(_) => notice_event::NoticeType::GroupFileUploaded,
+        notice_event::Notice::GroupEssenceChanged(_) => {
+            notice_event::NoticeType::GroupEssenceChanged
+        }
+        notice_event::Notice::GroupMemberUniqueTitleChanged(_) => {
+            notice_event::NoticeType::GroupMemberUniqueTitleChanged
+        }
+        notice_event::Notice::FriendIncrease(_) => notice_event::NoticeType::FriendIncrease,
+        notice_event::Notice::FriendDecrease(_) => notice_event::NoticeType::FriendDecrease,
+        _ => notice_event::NoticeType::Unknown,
+    };
+    EventStructure {
+        r#type: EventType::Notice.into(),
+        event: Some(event_structure::Event::Notice(NoticeEvent {
+            r#type: r#type.into(),
+            time,
+            notice_id,
+            notice: Some(notice),
+        })),
+    }
+}
+
+impl Event {
+    /// Convert a guild or friend event into a kritor notice.
+    ///
+    /// The bot joining or leaving a guild is told as the bot itself being the
+    /// member increased or decreased. Satori does not tell how the bot joined,
+    /// so it is guessed to be invited when there is an operator and approved
+    /// otherwise. kritor has no notice for guild updates, which are unsupported.
+    pub(crate) fn try_into_notice(self, ctx: &Context) -> Result<EventStructure, ConversionError> {
+        let time = (self.timestamp / 1000) as u64;
+        let notice_id = self.id.to_string();
+        let guild = self.guild.ok_or(ConversionError::MissingField("guild"));
+        let user = self.user.ok_or(ConversionError::MissingField("user"));
+        let operator = self.operator;
+        let operator_uin = operator.as_ref().map(|x| ctx.identities.uin(&x.id));
+        let operator_uid = operator.as_ref().map(|x| x.id.clone());
+        match self._type.as_str() {
+            "guild-added" => {
+                let target_uid = self.self_id;
+                Ok(notice(
+                    time,
+                    notice_id,
+                    notice_event::Notice::GroupMemberIncrease(GroupMemberIncreasedNotice {
+                        group_id: ctx.guild_identities.uin(&guild?.id),
+                        // a guess, as satori does not tell how the bot joined.
+                        r#type: match operator {
+                            Some(_) => GroupMemberIncreasedType::Invite,
+                            None => GroupMemberIncreasedType::Approve,
+                        }
+                        .into(),
+                        operator_uid,
+                        operator_uin,
+                        target_uin: ctx.identities.uin(&target_uid),
+                        target_uid,
+                    }),
+                ))
+            }
+            "guild-removed" => {
+                let target_uid = self.self_id;
+                let r#type = match &operator {
+                    Some(x) if x.id != target_uid => GroupMemberDecreasedType::KickMe,
+                    _ => GroupMemberDecreasedType::Leave,
+                };
+                Ok(notice(
+                    time,
+                    notice_id,
+                    notice_event::Notice::GroupMemberDecrease(GroupMemberDecreasedNotice {
+                        group_id: ctx.guild_identities.uin(&guild?.id),
+                        r#type: r#type.into(),
+                        operator_uid,
+                        operator_uin,
+                        target_uin: Some(ctx.identities.uin(&target_uid)),
+                        target_uid: Some(target_uid),
+                    }),
+                ))
+            }
+            "friend-added" | "friend-removed" => {
+                let user = user?;
+                let friend_uin = ctx.identities.uin(&user.id);
+                let friend_nick = user.nick.or(user.name).unwrap_or_default();
+                let friend_uid = user.id;
+                Ok(notice(
+                    time,
+                    notice_id,
+                    match self._type.as_str() {
+                        "friend-added" => {
+                            notice_event::Notice::FriendIncrease(FriendIncreasedNotice {
+                                friend_uid,
+                                friend_uin,
+                                friend_nick,
+                            })
+                        }
+                        _ => notice_event::Notice::FriendDecrease(FriendDecreasedNotice {
+                            friend_uid,
+                            friend_uin,
+                            friend_nick,
+                        }),
+                    },
+                ))
+            }
+            _ => Err(ConversionError::Unsupported(self._type)),
+        }
+    }
+
+    /// Convert an `internal` event into the kritor notice its `_type` is mapped to.
+    ///
+    /// Fields are read from `_data` by their OneBot names, such as `group_id`,
+    /// `user_id`, `target_id` and `operator_id`, falling back to the guild, user
+    /// and operator of the event. Events of other `_type`s, kritor having no
+    /// notice for them, are unsupported.
+    pub(crate) fn try_into_internal_notice(
+        self,
+        ctx: &Context,
+    ) -> Result<EventStructure, ConversionError> {
+        let internal_type = self
+            .internal_type
+            .ok_or(ConversionError::MissingField("_type"))?;
+        let kind = *ctx
+            .internal_notices
+            .get(&internal_type)
+            .ok_or_else(|| ConversionError::Unsupported(format!("internal:{}", internal_type)))?;
+        let data = self.internal_data.unwrap_or(Value::Null);
+        let time = (self.timestamp / 1000) as u64;
+        let notice_id = self.id.to_string();
+        let group = data_id(&data, "group_id").or(self.guild.map(|x| x.id));
+        let group_id = || {
+            group
+                .as_ref()
+                .map(|x| ctx.guild_identities.uin(x))
+                .ok_or(ConversionError::MissingField("group_id"))
+        };
+        let user = data_id(&data, "user_id").or(self.user.map(|x| x.id));
+        let operator = data_id(&data, "operator_id").or(self.operator.map(|x| x.id));
+        let uin = |uid: &str| ctx.identities.uin(uid);
+        let notice_event = match kind {
+            InternalNotice::Poke => {
+                // the user pokes the target, as in OneBot.
+                let operator_uid = operator
+                    .or(user)
+                    .ok_or(ConversionError::MissingField("user_id"))?;
+                let action = data_str(&data, "action");
+                let suffix = data_str(&data, "suffix");
+                let action_image = data_str(&data, "action_image");
+                match &group {
+                    Some(_) => {
+                        let target_uid = data_id(&data, "target_id")
+                            .ok_or(ConversionError::MissingField("target_id"))?;
+                        notice_event::Notice::GroupPoke(GroupPokeNotice {
+                            group_id: group_id()?,
+                            operator_uin: uin(&operator_uid),
+                            operator_uid,
+                            target_uin: uin(&target_uid),
+                            target_uid,
+                            action,
+                            suffix,
+                            action_image,
+                        })
+                    }
+                    None => notice_event::Notice::PrivatePoke(PrivatePokeNotice {
+                        operator_uin: uin(&operator_uid),
+                        operator_uid,
+                        action,
+                        suffix,
+                        action_image,
+                    }),
+                }
+            }
+            InternalNotice::FileUploaded => {
+                let operator_uid = user
+                    .or(operator)
+                    .ok_or(ConversionError::MissingField("user_id"))?;
+                let file = data
+                    .get("file")
+                    .ok_or(ConversionError::MissingField("file"))?;
+                let file_id = data_id(file, "id").unwrap_or_default();
+                let file_name = data_str(file, "name");
+                let file_size = file.get("size").and_then(|x| x.as_u64()).unwrap_or(0);
+                let url = data_str(file, "url");
+                match &group {
+                    Some(_) => notice_event::Notice::GroupFileUploaded(GroupFileUploadedNotice {
+                        group_id: group_id()?,
+                        operator_uin: uin(&operator_uid),
+                        operator_uid,
+                        file_id,
+                        file_sub_id: String::new(),
+                        file_name,
+                        file_size,
+                        expire_time: 0,
+                        biz: file.get("busid").and_then(|x| x.as_u64()).unwrap_or(0) as u32,
+                        url,
+                    }),
+                    None => notice_event::Notice::PrivateFileUploaded(PrivateFileUploadedNotice {
+                        operator_uin: uin(&operator_uid),
+                        operator_uid,
+                        file_id,
+                        file_sub_id: String::new(),
+                        file_name,
+                        file_size,
+                        expire_time: 0,
+                        url,
+                    }),
+                }
+            }
+            InternalNotice::EssenceChanged => {
+                let target_uid = data_id(&data, "sender_id")
+                    .or(user)
+                    .ok_or(ConversionError::MissingField("sender_id"))?;
+                notice_event::Notice::GroupEssenceChanged(GroupEssenceMessageNotice {
+                    group_id: group_id()?,
+                    // the operator may be unknown, as with essences set long ago.
+                    operator_uin: operator.as_deref().map_or(0, uin),
+                    operator_uid: operator.unwrap_or_default(),
+                    target_uin: uin(&target_uid),
+                    target_uid,
+                    message_id: data_id(&data, "message_id").unwrap_or_default(),
+                    is_set: data_str(&data, "sub_type") != "delete",
+                })
+            }
+            InternalNotice::TitleChanged => {
+                let target_uid = user.ok_or(ConversionError::MissingField("user_id"))?;
+                notice_event::Notice::GroupMemberUniqueTitleChanged(GroupUniqueTitleChangedNotice {
+                    group_id: group_id()?,
+                    target_uin: uin(&target_uid),
+                    target_uid,
+                    title: data_str(&data, "title"),
+                })
+            }
+        };
+        Ok(notice(time, notice_id, notice_event))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::message::Fixture;
+    use super::super::Dialect;
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_guild_notices() {
+        let fixture = Fixture::default();
+        let ctx = fixture.context(Dialect::Standard);
+        let event = |r#type: &str, user: &str, operator: Option<&str>| -> Event {
+            serde_json::from_value(json!({
+                "id": 1,
+                "type": r#type,
+                "platform": "qq",
+                "self_id": "10000",
+                "timestamp": 1000,
+                "guild": {"id": "123"},
+                "user": {"id": user},
+                "operator": operator.map(|x| json!({"id": x})),
+            }))
+            .unwrap()
+        };
+        let decreased = |event: Event| match event.try_into_notice(&ctx).unwrap().event {
+            Some(event_structure::Event::Notice(NoticeEvent {
+                notice: Some(notice_event::Notice::GroupMemberDecrease(x)),
+                ..
+            })) => x,
+            _ => panic!("not a member decreased notice"),
+        };
+        let notice = decreased(event("guild-removed", "10000", Some("42")));
+        assert_eq!(notice.r#type(), GroupMemberDecreasedType::KickMe);
+        assert_eq!(notice.group_id, 123);
+        assert_eq!(notice.target_uin, Some(10000));
+        let notice = decreased(event("guild-removed", "10000", None));
+        assert_eq!(notice.r#type(), GroupMemberDecreasedType::Leave);
+        assert!(matches!(
+            event("guild-updated", "10000", None).try_into_kritor(&ctx),
+            Err(ConversionError::Unsupported(_))
+        ));
+        // members other than the bot were not asked for.
+        assert!(event("guild-member-removed", "43", Some("42"))
+            .try_into_kritor(&ctx)
+            .is_err());
+
+        match event("guild-added", "42", Some("42"))
+            .try_into_notice(&ctx)
+            .unwrap()
+            .event
+        {
+            Some(event_structure::Event::Notice(NoticeEvent {
+                notice: Some(notice_event::Notice::GroupMemberIncrease(x)),
+                ..
+            })) => {
+                assert_eq!(x.r#type(), GroupMemberIncreasedType::Invite);
+                assert_eq!(x.target_uid, "10000");
+            }
+            _ => panic!("not a member increased notice"),
+        }
+    }
+
+    #[test]
+    fn test_internal_notices() {
+        let fixture = Fixture {
+            internal_notices: internal_notices(&HashMap::from([(
+                "chronocat/poke".to_string(),
+                InternalNotice::Poke,
+            )])),
+            ..Default::default()
+        };
+        let ctx = fixture.context(Dialect::Standard);
+        let event = |r#type: &str, data: Value| -> Event {
+            serde_json::from_value(json!({
+                "id": 1,
+                "type": "internal",
+                "platform": "qq",
+                "self_id": "10000",
+                "timestamp": 1000,
+                "_type": r#type,
+                "_data": data,
+            }))
+            .unwrap()
+        };
+        let notice = |event: Event| match event.try_into_kritor(&ctx).unwrap().event {
+            Some(event_structure::Event::Notice(NoticeEvent {
+                notice: Some(notice),
+                ..
+            })) => notice,
+            _ => panic!("not a notice"),
+        };
+        match notice(event(
+            "chronocat/poke",
+            json!({"group_id": 123, "user_id": 42, "target_id": "10000"}),
+        )) {
+            notice_event::Notice::GroupPoke(x) => {
+                assert_eq!(x.group_id, 123);
+                assert_eq!(x.operator_uin, 42);
+                assert_eq!(x.target_uid, "10000");
+            }
+            _ => panic!("not a group poke"),
+        }
+        match notice(event(
+            "group_upload",
+            json!({"group_id": 123, "user_id": 42, "file": {"id": "/abc", "name": "a.txt", "size": 3, "busid": 102}}),
+        )) {
+            notice_event::Notice::GroupFileUploaded(x) => {
+                assert_eq!(x.file_id, "/abc");
+                assert_eq!(x.file_size, 3);
+                assert_eq!(x.biz, 102);
+            }
+            _ => panic!("not a group file uploaded"),
+        }
+        match notice(event(
+            "essence",
+            json!({"group_id": 123, "sender_id": 43, "operator_id": 42, "message_id": 7, "sub_type": "delete"}),
+        )) {
+            notice_event::Notice::GroupEssenceChanged(x) => {
+                assert_eq!(x.message_id, "7");
+                assert!(!x.is_set);
+            }
+            _ => panic!("not an essence change"),
+        }
+        match notice(event(
+            "essence",
+            json!({"group_id": 123, "sender_id": 43, "message_id": 7}),
+        )) {
+            notice_event::Notice::GroupEssenceChanged(x) => {
+                assert_eq!(x.operator_uin, 0);
+                assert_eq!(x.operator_uid, "");
+                assert!(ctx.identities.uid(0).is_none());
+            }
+            _ => panic!("not an essence change"),
+        }
+        for r#type in ["unheard", "honor"] {
+            assert!(matches!(
+                event(r#type, json!({})).try_into_kritor(&ctx),
+                Err(ConversionError::Unsupported(_))
+            ));
+        }
+    }
+}
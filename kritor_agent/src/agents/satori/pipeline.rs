@@ -0,0 +1,174 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use kritor::event::event_structure;
+use serde::Deserialize;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+use super::dead_letter::DeadLetters;
+use super::notice::InternalNotice;
+use super::session::{Session, Source};
+use super::{
+    message, schema, BusEvent, Dialect, DirectChannels, EventBus, IdentityRegistry, LoginRegistry,
+    MessageIndex, ResourceStore,
+};
+
+/// Turns satori signals into kritor events on the bus, wherever they come from,
+/// be it the event websocket or webhooks.
+pub struct Pipeline {
+    pub events: Arc<EventBus>,
+    pub resources: Arc<ResourceStore>,
+    pub identities: Arc<IdentityRegistry>,
+    pub guild_identities: Arc<IdentityRegistry>,
+    pub messages: Arc<MessageIndex>,
+    pub channels: Arc<DirectChannels>,
+    pub logins: Arc<LoginRegistry>,
+    pub dialect: Dialect,
+    /// where events go to have the md5 of their images computed, if enabled.
+    pub image_md5: Option<Outbox>,
+    /// the most bytes of an image downloaded for its md5.
+    pub image_md5_max_size: u64,
+    pub http: reqwest::Client,
+    pub session: Mutex<Session>,
+    pub dead_letters: Arc<DeadLetters>,
+    pub internal_notices: Arc<HashMap<String, InternalNotice>>,
+}
+
+/// Events being completed off the hot path, published in the order they came
+/// in once they are done.
+pub struct Outbox {
+    sender: mpsc::UnboundedSender<JoinHandle<BusEvent>>,
+}
+
+impl Outbox {
+    pub fn new(events: Arc<EventBus>) -> Self {
+        let (sender, mut receiver) = mpsc::unbounded_channel::<JoinHandle<BusEvent>>();
+        tokio::spawn(async move {
+            while let Some(event) = receiver.recv().await {
+                match event.await {
+                    Ok(event) => events.publish(event),
+                    Err(e) => log::error!("Failed to complete an event: {}", e),
+                }
+            }
+        });
+        Self { sender }
+    }
+    fn send(&self, event: JoinHandle<BusEvent>) {
+        let _ = self.sender.send(event);
+    }
+}
+
+impl Pipeline {
+    fn context(&self) -> message::Context<'_> {
+        message::Context {
+            resources: &self.resources,
+            identities: &self.identities,
+            guild_identities: &self.guild_identities,
+            messages: &self.messages,
+            channels: &self.channels,
+            dialect: self.dialect,
+            internal_notices: &self.internal_notices,
+        }
+    }
+    /// Handle a signal of the satori server, ignoring all but EVENT and READY.
+    ///
+    /// Signals that can not be handled are recorded as dead letters.
+    pub async fn dispatch(&self, data: serde_json::Value, source: Source) {
+        let op = data.get("op").and_then(|x| x.as_u64());
+        if op != Some(0) && op != Some(4) {
+            return;
+        }
+        let Some(body) = data.get("body") else {
+            self.dead_letters
+                .record(data.to_string(), "signal without body".into());
+            return;
+        };
+        // READY, listing the logins.
+        if op == Some(4) {
+            match schema::Ready::deserialize(body) {
+                Ok(ready) => {
+                    log::info!("satori server is ready with {} logins", ready.logins.len());
+                    self.logins.reset(ready.logins);
+                }
+                Err(e) => self
+                    .dead_letters
+                    .record(body.to_string(), format!("malformed READY: {}", e)),
+            }
+            return;
+        }
+        let event = match schema::Event::deserialize(body) {
+            Ok(event) => event,
+            Err(e) => {
+                self.dead_letters
+                    .record(body.to_string(), format!("malformed event: {}", e));
+                return;
+            }
+        };
+        if !self.session.lock().unwrap().accept(event.id, source) {
+            log::debug!("skipping replayed event {}", event.id);
+            return;
+        }
+        self.logins.apply(&event);
+        let self_id = event.self_id.clone();
+        let mut ev = match event.try_into_kritor(&self.context()) {
+            Ok(ev) => ev,
+            Err(e @ schema::ConversionError::Unsupported(_)) => {
+                self.dead_letters
+                    .record_unsupported(body.to_string(), e.to_string());
+                return;
+            }
+            Err(e) => {
+                self.dead_letters.record(body.to_string(), e.to_string());
+                return;
+            }
+        };
+        let Some(outbox) = &self.image_md5 else {
+            log::debug!("received message event:{:?}", ev);
+            self.events.publish(BusEvent { self_id, event: ev });
+            return;
+        };
+        // downloading images must not hold up the following signals.
+        let http = self.http.clone();
+        let max_size = self.image_md5_max_size;
+        outbox.send(tokio::spawn(async move {
+            if let Some(event_structure::Event::Message(message)) = &mut ev.event {
+                message::media::fill_image_md5(&mut message.elements, &http, max_size).await;
+            }
+            log::debug!("received message event:{:?}", ev);
+            BusEvent { self_id, event: ev }
+        }));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agents::satori::{EventBusConfig, EventFilter};
+    use futures_util::StreamExt;
+    use kritor::event::{EventStructure, EventType};
+
+    #[tokio::test]
+    async fn test_outbox_order() {
+        let events = Arc::new(EventBus::new(&EventBusConfig::default()).unwrap());
+        let mut stream = events.subscribe(EventFilter::default());
+        let outbox = Outbox::new(events.clone());
+        let event = |r#type: EventType| BusEvent {
+            self_id: String::new(),
+            event: EventStructure {
+                r#type: r#type.into(),
+                event: None,
+            },
+        };
+        let slow = event(EventType::Message);
+        outbox.send(tokio::spawn(async move {
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+            slow
+        }));
+        let fast = event(EventType::Notice);
+        outbox.send(tokio::spawn(async move { fast }));
+        for r#type in [EventType::Message, EventType::Notice] {
+            assert_eq!(stream.next().await.unwrap().unwrap().r#type, r#type as i32);
+        }
+    }
+}
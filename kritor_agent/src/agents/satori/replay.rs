@@ -0,0 +1,165 @@
+use std::collections::VecDeque;
+use std::path::Path;
+
+use base64::Engine;
+use kritor::event::{event_structure::Event, EventStructure};
+use prost::Message;
+
+use super::appender::{Appender, Retention};
+use super::bus::BusEvent;
+
+/// The latest events, numbered from 1, for subscribers to catch up with.
+///
+/// If a path is given, events are appended to the file as
+/// `{id}\t{self_id}\t{base64 protobuf}` lines, so that they survive restarts.
+/// The file is rewritten with only the buffered events once it holds twice as many.
+#[derive(Default)]
+pub struct ReplayBuffer {
+    events: VecDeque<(u64, BusEvent)>,
+    capacity: usize,
+    next_id: u64,
+    writer: Option<Appender>,
+}
+
+/// The time of the event, in seconds.
+pub fn event_time(event: &EventStructure) -> u64 {
+    match &event.event {
+        Some(Event::Message(x)) => x.time,
+        Some(Event::Notice(x)) => x.time,
+        Some(Event::Request(x)) => x.time,
+        None => 0,
+    }
+}
+
+fn encode(id: u64, event: &BusEvent) -> String {
+    format!(
+        "{}\t{}\t{}\n",
+        id,
+        event.self_id,
+        base64::engine::general_purpose::STANDARD.encode(event.event.encode_to_vec())
+    )
+}
+
+fn decode(line: &str) -> Option<(u64, BusEvent)> {
+    let mut split = line.splitn(3, '\t');
+    let id = split.next()?.parse().ok()?;
+    let self_id = split.next()?.to_string();
+    let buf = base64::engine::general_purpose::STANDARD
+        .decode(split.next()?)
+        .ok()?;
+    let event = EventStructure::decode(buf.as_slice()).ok()?;
+    Some((id, BusEvent { self_id, event }))
+}
+
+impl ReplayBuffer {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            next_id: 1,
+            ..Default::default()
+        }
+    }
+    /// Load the buffer from the file, which is created on the first event if missing.
+    pub fn open(capacity: usize, path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let mut buffer = Self::new(capacity);
+        let path = path.as_ref().to_path_buf();
+        let content = match std::fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e),
+        };
+        for line in content.lines() {
+            match decode(line) {
+                Some((id, event)) => buffer.insert(id, event),
+                None => log::warn!("Ignoring malformed replay line"),
+            }
+        }
+        let retention = Retention {
+            lines: capacity,
+            key: None,
+        };
+        buffer.writer = Some(Appender::with_retention(path, retention, content.lines())?);
+        Ok(buffer)
+    }
+    fn insert(&mut self, id: u64, event: BusEvent) {
+        self.next_id = self.next_id.max(id + 1);
+        if self.capacity == 0 {
+            return;
+        }
+        if self.events.len() >= self.capacity {
+            self.events.pop_front();
+        }
+        self.events.push_back((id, event));
+    }
+    /// Buffer an event, returning its id.
+    pub fn push(&mut self, event: BusEvent) -> u64 {
+        let id = self.next_id;
+        if self.capacity > 0 {
+            if let Some(writer) = &self.writer {
+                writer.append(encode(id, &event));
+            }
+        }
+        self.insert(id, event);
+        id
+    }
+    /// The buffered events after the id, oldest first.
+    pub fn after(&self, id: u64) -> impl Iterator<Item = &(u64, BusEvent)> {
+        self.events.iter().filter(move |(x, _)| *x > id)
+    }
+    /// The buffered events from the time on, in seconds, oldest first.
+    ///
+    /// Times come from the platform and are not in order, prefer [`ReplayBuffer::after`].
+    pub fn since(&self, time: u64) -> impl Iterator<Item = &(u64, BusEvent)> {
+        self.events
+            .iter()
+            .filter(move |(_, x)| event_time(&x.event) >= time)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kritor::event::{EventType, NoticeEvent};
+
+    fn notice(time: u64) -> BusEvent {
+        BusEvent {
+            self_id: "10000".into(),
+            event: EventStructure {
+                r#type: EventType::Notice.into(),
+                event: Some(Event::Notice(NoticeEvent {
+                    time,
+                    ..Default::default()
+                })),
+            },
+        }
+    }
+
+    #[test]
+    fn test_replay_persistence() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("replay");
+        let mut buffer = ReplayBuffer::open(2, &path).unwrap();
+        for time in 1..=5 {
+            assert_eq!(buffer.push(notice(time)), time);
+        }
+        let times = |buffer: &ReplayBuffer, since| {
+            buffer
+                .since(since)
+                .map(|(_, x)| event_time(&x.event))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(times(&buffer, 0), vec![4, 5]);
+        assert_eq!(times(&buffer, 5), vec![5]);
+        assert_eq!(
+            buffer.after(4).map(|(id, _)| *id).collect::<Vec<_>>(),
+            vec![5]
+        );
+        // dropping the buffer finishes writing the file, compacted on the 5th event.
+        drop(buffer);
+        assert_eq!(std::fs::read_to_string(&path).unwrap().lines().count(), 3);
+
+        let mut buffer = ReplayBuffer::open(2, &path).unwrap();
+        assert_eq!(times(&buffer, 0), vec![4, 5]);
+        assert_eq!(buffer.push(notice(6)), 6);
+    }
+}
@@ -0,0 +1,69 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use kritor::common::ForwardMessageBody;
+
+/// How many forward bundles are kept before the oldest ones are evicted.
+const FORWARD_CAPACITY: usize = 1024;
+
+/// Resources that satori has no way to keep for us, held locally by the agent.
+///
+/// Currently these are forward (merged) message bundles, either uploaded through
+/// `UploadForwardMessage` or received as nested `<message forward>` elements.
+#[derive(Default)]
+pub struct ResourceStore {
+    forwards: Mutex<Forwards>,
+    counter: AtomicU64,
+}
+
+#[derive(Default)]
+struct Forwards {
+    bundles: HashMap<String, Vec<ForwardMessageBody>>,
+    /// insertion order, used for eviction.
+    order: VecDeque<String>,
+}
+
+impl ResourceStore {
+    /// Store a forward bundle and return the generated res_id referring to it.
+    pub fn put_forward(&self, messages: Vec<ForwardMessageBody>) -> String {
+        let res_id = self.generate_res_id();
+        let mut forwards = self.forwards.lock().unwrap();
+        if forwards.order.len() >= FORWARD_CAPACITY {
+            if let Some(oldest) = forwards.order.pop_front() {
+                forwards.bundles.remove(&oldest);
+            }
+        }
+        forwards.order.push_back(res_id.clone());
+        forwards.bundles.insert(res_id.clone(), messages);
+        res_id
+    }
+    pub fn get_forward(&self, res_id: &str) -> Option<Vec<ForwardMessageBody>> {
+        self.forwards.lock().unwrap().bundles.get(res_id).cloned()
+    }
+    fn generate_res_id(&self) -> String {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|x| x.as_nanos())
+            .unwrap_or_default();
+        let count = self.counter.fetch_add(1, Ordering::Relaxed);
+        format!("kritor_agent:{:x}{:04x}", nanos, count & 0xffff)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_forward_eviction() {
+        let store = ResourceStore::default();
+        let first = store.put_forward(vec![]);
+        assert!(store.get_forward(&first).is_some());
+        for _ in 0..FORWARD_CAPACITY {
+            store.put_forward(vec![]);
+        }
+        assert!(store.get_forward(&first).is_none());
+    }
+}
@@ -1,9 +1,16 @@
-use kritor::common::{push_message_body::Sender, PrivateSender, Scene};
-use serde::Deserialize;
+use kritor::common::element::Data as KritorElementData;
+use kritor::common::element::ElementType as KritorElementType;
+use kritor::common::{
+    push_message_body::Sender, Element as KritorElement, JsonElement, KeyboardElement, KeyboardRow,
+    PrivateSender, ReplyElement, Scene, TextElement,
+};
+use serde::{Deserialize, Serialize};
 
 use serde_json::Value;
 use serde_repr::Deserialize_repr;
 
+use super::message::Context;
+
 #[derive(Deserialize, PartialEq, Debug, Clone)]
 pub struct Login {
     /// 用户对象
@@ -29,6 +36,13 @@ pub struct User {
     pub is_bot: Option<bool>,
 }
 
+/// READY 信令的内容
+#[derive(Deserialize, Debug, Clone)]
+pub struct Ready {
+    /// 登录信息
+    pub logins: Vec<Login>,
+}
+
 #[derive(Deserialize_repr, PartialEq, Debug, Clone)]
 #[repr(u8)]
 pub enum Status {
@@ -47,7 +61,7 @@ pub enum Status {
 #[derive(Deserialize, Default, Clone)]
 pub struct Event {
     /// 事件 ID
-    pub id: i32,
+    pub id: i64,
     /// 事件类型
     #[serde(rename = "type")]
     pub _type: String,
@@ -77,9 +91,15 @@ pub struct Event {
     pub role: Option<GuildRole>,
     /// 事件的目标用户
     pub user: Option<User>,
+    /// 原生事件类型，仅 internal 事件
+    #[serde(rename = "_type")]
+    pub internal_type: Option<String>,
+    /// 原生事件数据，仅 internal 事件
+    #[serde(rename = "_data")]
+    pub internal_data: Option<Value>,
 }
 
-#[derive(Deserialize, Clone)]
+#[derive(Deserialize, Serialize, Clone)]
 pub struct Argv {
     /// 指令名称
     pub name: String,
@@ -161,6 +181,15 @@ pub struct Message {
     pub updated_at: Option<i64>,
 }
 
+/// 分页列表
+#[derive(Deserialize, Clone)]
+pub struct List<T> {
+    /// 数据
+    pub data: Vec<T>,
+    /// 下一页的令牌
+    pub next: Option<String>,
+}
+
 #[derive(Deserialize, Clone)]
 pub struct GuildRole {
     /// 角色 ID
@@ -169,94 +198,507 @@ pub struct GuildRole {
     pub name: Option<String>,
 }
 
-impl TryInto<kritor::event::EventStructure> for Event {
-    type Error = ();
-    fn try_into(self) -> Result<kritor::event::EventStructure, Self::Error> {
+/// Why a satori event can not be converted into a kritor one.
+#[derive(Debug)]
+pub enum ConversionError {
+    /// kritor has no counterpart of the event type.
+    Unsupported(String),
+    /// the event lacks a field required by its type.
+    MissingField(&'static str),
+    /// a message event was expected, but it converts into another kind of event.
+    NotMessage,
+    /// the message content can not be parsed, or converted into kritor elements.
+    Content(super::message::Error),
+    Json(serde_json::Error),
+}
+impl std::fmt::Display for ConversionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Unsupported(r#type) => write!(f, "unsupported event type {}", r#type),
+            Self::MissingField(field) => write!(f, "missing field {}", field),
+            Self::NotMessage => write!(f, "not a message event"),
+            Self::Content(e) => write!(f, "invalid message content: {}", e),
+            Self::Json(e) => write!(f, "{}", e),
+        }
+    }
+}
+impl std::error::Error for ConversionError {}
+
+impl Event {
+    /// Convert the event into a kritor one.
+    pub(crate) fn try_into_kritor(
+        self,
+        ctx: &Context,
+    ) -> Result<kritor::event::EventStructure, ConversionError> {
+        if let "login-added" | "login-removed" | "login-updated" = self._type.as_str() {
+            return Ok(super::link::core_event(
+                self.id.to_string(),
+                (self.timestamp / 1000) as u64,
+            ));
+        }
+        if self._type == "internal" {
+            return self.try_into_internal_notice(ctx);
+        }
+        if super::notice::NOTICE_TYPES.contains(&self._type.as_str()) {
+            return self.try_into_notice(ctx);
+        }
+        let message_seq = match (&self.channel, &self.message) {
+            (Some(channel), Some(message)) if !message.id.is_empty() => {
+                ctx.messages.seq(&channel.id, &message.id)
+            }
+            _ => 0,
+        };
+        let contact = self.contact();
+        // remember direct channels to reply to them without `user.channel.create`,
+        // but not those of messages the bot sent, whose user is the bot itself.
+        if let (Some(channel), Some(user), Some(contact)) = (&self.channel, &self.user, &contact) {
+            if channel._type == ChannelType::DIRECT && user.id != self.self_id {
+                ctx.channels.insert(
+                    self.self_id.clone(),
+                    user.id.clone(),
+                    contact.sub_peer.clone(),
+                    channel.id.clone(),
+                );
+            }
+        }
+        let contact = contact.map(|x| ctx.kritor_contact(x));
+        let scene = contact
+            .as_ref()
+            .and_then(|x| Scene::try_from(x.scene).ok())
+            .unwrap_or(Scene::Unspecified);
         let figure_sender = || {
-            if let Some(user) = self.user {
-                if let Some(channel) = &self.channel {
-                    return match channel._type {
-                        ChannelType::DIRECT => Some(Sender::Private(PrivateSender {
-                            nick: user.nick.unwrap_or_default(),
-                            uin: user.id.parse().unwrap_or_default(),
-                            uid: None,
-                        })),
-                        ChannelType::TEXT => Some(Sender::Group(kritor::common::GroupSender {
-                            nick: user.nick.unwrap_or_default(),
-                            uin: user.id.parse().unwrap_or_default(),
-                            uid: None,
-                            group_id: channel.peer().to_owned(),
-                        })),
-                        _ => None,
-                    };
+            let user = self.user?;
+            let contact = contact.as_ref()?;
+            match scene {
+                Scene::Friend | Scene::Stranger | Scene::StrangerFromGroup => {
+                    Some(Sender::Private(PrivateSender {
+                        nick: user.nick.unwrap_or_default(),
+                        uin: ctx.identities.uin(&user.id),
+                        uid: Some(user.id),
+                    }))
                 }
+                Scene::Group => Some(Sender::Group(kritor::common::GroupSender {
+                    nick: user.nick.unwrap_or_default(),
+                    uin: ctx.identities.uin(&user.id),
+                    uid: Some(user.id),
+                    group_id: contact.peer.clone(),
+                })),
+                Scene::Guild => Some(Sender::Guild(kritor::common::GuildSender {
+                    guild_id: contact.peer.clone(),
+                    channel_id: contact.sub_peer.clone().unwrap_or_default(),
+                    tiny_id: ctx.identities.uin(&user.id),
+                    nick: user.nick.unwrap_or_default(),
+                    ..Default::default()
+                })),
+                _ => None,
             }
-            None
         };
         match self._type.as_str() {
             "message-created" => Ok(kritor::event::EventStructure {
+                r#type: kritor::event::EventType::Message.into(),
+                event: Some(kritor::event::event_structure::Event::Message(
+                    kritor::common::PushMessageBody {
+                        time: (self.timestamp / 1000) as u64,
+                        message_seq,
+                        scene: scene.into(),
+                        contact: contact.clone(),
+                        sender: figure_sender(),
+                        elements: super::message::Parser::new(
+                            self.message
+                                .as_ref()
+                                .ok_or(ConversionError::MissingField("message"))?
+                                .content
+                                .as_str(),
+                        )
+                        .parse()
+                        .and_then(|r| r.try_into_kritor_elements(ctx))
+                        .map_err(ConversionError::Content)?,
+                        message_id: self
+                            .message
+                            .ok_or(ConversionError::MissingField("message"))?
+                            .id,
+                    },
+                )),
+            }),
+            // kritor has no event for button clicks, so they are delivered as a message
+            // holding a reply to the clicked message and a keyboard with the clicked button.
+            "interaction/button" => Ok(kritor::event::EventStructure {
                 r#type: kritor::event::EventType::Message.into(),
                 event: Some(kritor::event::event_structure::Event::Message(
                     kritor::common::PushMessageBody {
                         time: (self.timestamp / 1000) as u64,
                         message_seq: 0,
-                        scene: self.channel.as_ref().map(|x|x.scene()).unwrap_or(Scene::Unspecified).into(),
-                        // contact: self.channel.and_then(|x| x.try_into().ok()),
+                        scene: scene.into(),
+                        contact: contact.clone(),
                         sender: figure_sender(),
                         elements: {
-                            match super::message::Parser::new(
-                                self.message.as_ref().ok_or(())?.content.as_str(),
-                            )
-                            .parse()
-                            .and_then(|r| r.try_into_kritor_elements())
-                            {
-                                Ok(r) => r,
-                                Err(e) => {
-                                    log::error!("Failed to parse message content: {:?}, message set to empty", e);
-                                    vec![]
-                                }
+                            let mut elements = Vec::new();
+                            if let Some(message) = self.message {
+                                elements.push(KritorElement {
+                                    r#type: KritorElementType::Reply.into(),
+                                    data: Some(KritorElementData::Reply(ReplyElement {
+                                        message_id: message.id,
+                                    })),
+                                });
                             }
+                            elements.push(KritorElement {
+                                r#type: KritorElementType::Keyboard.into(),
+                                data: Some(KritorElementData::Keyboard(KeyboardElement {
+                                    rows: vec![KeyboardRow {
+                                        buttons: vec![kritor::common::Button {
+                                            id: self
+                                                .button
+                                                .ok_or(ConversionError::MissingField("button"))?
+                                                .id,
+                                            ..Default::default()
+                                        }],
+                                    }],
+                                    ..Default::default()
+                                })),
+                            });
+                            elements
                         },
-                        message_id: self.message.ok_or(())?.id,
+                        message_id: String::new(),
                     },
                 )),
             }),
-            _ => Err(()),
+            // delivered as a message with the reconstructed command line,
+            // followed by the raw argv as a json element.
+            "interaction/command" => {
+                let argv = self.argv.ok_or(ConversionError::MissingField("argv"))?;
+                Ok(kritor::event::EventStructure {
+                    r#type: kritor::event::EventType::Message.into(),
+                    event: Some(kritor::event::event_structure::Event::Message(
+                        kritor::common::PushMessageBody {
+                            time: (self.timestamp / 1000) as u64,
+                            message_seq,
+                            scene: scene.into(),
+                            contact: contact.clone(),
+                            sender: figure_sender(),
+                            elements: vec![
+                                KritorElement {
+                                    r#type: KritorElementType::Text.into(),
+                                    data: Some(KritorElementData::Text(TextElement {
+                                        text: argv.to_command_line(),
+                                    })),
+                                },
+                                KritorElement {
+                                    r#type: KritorElementType::Json.into(),
+                                    data: Some(KritorElementData::Json(JsonElement {
+                                        json: serde_json::to_string(&argv)
+                                            .map_err(ConversionError::Json)?,
+                                    })),
+                                },
+                            ],
+                            message_id: self.message.map(|x| x.id).unwrap_or_default(),
+                        },
+                    )),
+                })
+            }
+            _ => Err(ConversionError::Unsupported(self._type.clone())),
+        }
+    }
+}
+
+impl Event {
+    /// Where the event happened, with the guild as peer and the channel as sub_peer
+    /// if they differ.
+    fn contact(&self) -> Option<kritor::common::Contact> {
+        let guild = self
+            .guild
+            .as_ref()
+            .or(self.message.as_ref().and_then(|x| x.guild.as_ref()));
+        let user = self
+            .user
+            .as_ref()
+            .or(self.message.as_ref().and_then(|x| x.user.as_ref()));
+        self.channel.as_ref().map(|x| x.contact(guild, user))
+    }
+}
+
+impl Message {
+    /// Convert a message got from the api into a kritor one.
+    ///
+    /// The channel and guild are used if satori does not tell the message's own.
+    pub(crate) fn try_into_kritor(
+        mut self,
+        channel_id: String,
+        channel_type: ChannelType,
+        guild_id: Option<String>,
+        ctx: &Context,
+    ) -> Result<kritor::common::PushMessageBody, ConversionError> {
+        let channel = self.channel.take().unwrap_or(Channel {
+            id: channel_id,
+            _type: channel_type,
+            name: None,
+            parent_id: None,
+        });
+        if let Some(id) = guild_id {
+            self.guild.get_or_insert(Guild {
+                id,
+                name: None,
+                avatar: None,
+            });
+        }
+        let event = Event {
+            _type: "message-created".into(),
+            timestamp: self.created_at.unwrap_or_default(),
+            channel: Some(channel),
+            guild: self.guild.clone(),
+            member: self.member.clone(),
+            user: self.user.clone(),
+            message: Some(self),
+            ..Default::default()
+        };
+        match event.try_into_kritor(ctx)?.event {
+            Some(kritor::event::event_structure::Event::Message(x)) => Ok(x),
+            _ => Err(ConversionError::NotMessage),
         }
     }
 }
 
+impl Argv {
+    /// Reconstruct the command line, e.g. `/echo foo "bar baz" --times 2 --loud`.
+    ///
+    /// Boolean options are written as `--name` or `--no-name`.
+    pub fn to_command_line(&self) -> String {
+        fn arg(value: &Value) -> String {
+            let s = match value {
+                Value::String(s) => s.clone(),
+                x => x.to_string(),
+            };
+            if s.is_empty() || s.contains(char::is_whitespace) {
+                format!("{:?}", s)
+            } else {
+                s
+            }
+        }
+        let mut line = format!("/{}", self.name);
+        for argument in &self.arguments {
+            line.push(' ');
+            line.push_str(&arg(argument));
+        }
+        if let Value::Object(options) = &self.options {
+            for (name, value) in options {
+                match value {
+                    Value::Bool(true) => line.push_str(&format!(" --{}", name)),
+                    Value::Bool(false) => line.push_str(&format!(" --no-{}", name)),
+                    Value::Null => {}
+                    x => line.push_str(&format!(" --{} {}", name, arg(x))),
+                }
+            }
+        }
+        line
+    }
+}
+
 impl Channel {
     pub fn scene(&self) -> Scene {
         match self._type {
             ChannelType::TEXT => Scene::Group,
             ChannelType::DIRECT => Scene::Friend,
-            _ => Scene::Group,
+            // voice channels only exist in guilds.
+            ChannelType::VOICE => Scene::Guild,
+            // categories hold channels, not messages.
+            ChannelType::CATEGORY => Scene::Unspecified,
         }
     }
-    pub fn into_peer(self) -> String {
-        if let Some(peer) = self.id.strip_prefix("private:") {
-            peer.into()
-        } else {
-            self.id
+    /// The kritor contact of the channel.
+    ///
+    /// Direct channels have the user as peer, and the guild as sub_peer if it is
+    /// a temporary session from the guild. Other channels have the guild as peer
+    /// and themselves as sub_peer in the guild scene, unless the channel is the
+    /// guild itself, as with QQ groups, or there is no guild.
+    ///
+    /// The ids are the platform ones, see `Context::kritor_contact`.
+    pub fn contact(&self, guild: Option<&Guild>, user: Option<&User>) -> kritor::common::Contact {
+        if self._type == ChannelType::DIRECT {
+            return kritor::common::Contact {
+                scene: if guild.is_some() {
+                    Scene::StrangerFromGroup
+                } else {
+                    Scene::Friend
+                }
+                .into(),
+                // the channel itself is all we know without the user.
+                peer: user.map(|x| &x.id).unwrap_or(&self.id).clone(),
+                sub_peer: guild.map(|x| x.id.clone()),
+            };
         }
-    }
-    pub fn peer(&self) -> &str {
-        if let Some(peer) = self.id.strip_prefix("private:") {
-            peer
-        } else {
-            &self.id
+        let peer = guild.map(|x| x.id.as_str()).unwrap_or(&self.id);
+        let sub_peer = (peer != self.id).then(|| self.id.clone());
+        kritor::common::Contact {
+            // text channels other than the guild itself are guild channels.
+            scene: match self.scene() {
+                Scene::Group if sub_peer.is_some() => Scene::Guild,
+                scene => scene,
+            }
+            .into(),
+            peer: peer.to_string(),
+            sub_peer,
         }
     }
 }
 impl TryInto<kritor::common::Contact> for Channel {
-    type Error = ();
+    type Error = std::convert::Infallible;
 
     fn try_into(self) -> Result<kritor::common::Contact, Self::Error> {
-        Ok(kritor::common::Contact {
-            scene: self.scene().into(),
-            peer: self.into_peer(),
-            sub_peer: None,
-        })
+        Ok(self.contact(None, None))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_channel_contact() {
+        let channel: Channel = serde_json::from_value(json!({
+            "id": "456",
+            "type": 0,
+            "parent_id": "789",
+        }))
+        .unwrap();
+        let guild: Guild = serde_json::from_value(json!({"id": "123"})).unwrap();
+        let contact = channel.contact(Some(&guild), None);
+        assert_eq!(contact.scene, i32::from(Scene::Guild));
+        assert_eq!(contact.peer, "123");
+        assert_eq!(contact.sub_peer.as_deref(), Some("456"));
+        // the parent is a category, not where the message is.
+        let contact = channel.contact(None, None);
+        assert_eq!(contact.peer, "456");
+        assert_eq!(contact.sub_peer, None);
+
+        // QQ groups are guilds with a single channel of the same id.
+        let guild: Guild = serde_json::from_value(json!({"id": "456"})).unwrap();
+        let contact = channel.contact(Some(&guild), None);
+        assert_eq!(contact.scene, i32::from(Scene::Group));
+        assert_eq!(contact.sub_peer, None);
+
+        let channel: Channel = serde_json::from_value(json!({"id": "d42", "type": 1})).unwrap();
+        let user: User = serde_json::from_value(json!({"id": "10086"})).unwrap();
+        let contact = channel.contact(None, Some(&user));
+        assert_eq!(contact.scene, i32::from(Scene::Friend));
+        assert_eq!(contact.peer, "10086");
+        let contact = channel.contact(Some(&guild), Some(&user));
+        assert_eq!(contact.scene, i32::from(Scene::StrangerFromGroup));
+        assert_eq!(contact.sub_peer.as_deref(), Some("456"));
+
+        // kritor sees uins, of guilds apart from users.
+        let fixture = super::super::message::Fixture::default();
+        let ctx = fixture.context(Default::default());
+        let channel: Channel = serde_json::from_value(json!({"id": "c1", "type": 0})).unwrap();
+        let guild: Guild = serde_json::from_value(json!({"id": "g1"})).unwrap();
+        let contact = ctx.kritor_contact(channel.contact(Some(&guild), None));
+        let guild_uin = fixture.guild_identities.uin("g1");
+        assert_eq!(contact.peer, guild_uin.to_string());
+        assert_eq!(
+            fixture
+                .guild_identities
+                .resolve_peer(contact.sub_peer.unwrap()),
+            "c1"
+        );
+        assert_eq!(fixture.identities.uid(guild_uin), None);
+    }
+
+    /// The message an interaction in a QQ group is delivered as.
+    fn interaction(r#type: &str, extra: Value) -> kritor::common::PushMessageBody {
+        let fixture = super::super::message::Fixture::default();
+        let ctx = fixture.context(Default::default());
+        let mut event = json!({
+            "id": 1,
+            "type": r#type,
+            "platform": "qq",
+            "self_id": "10000",
+            "timestamp": 1000,
+            "channel": {"id": "456", "type": 0},
+            "guild": {"id": "456"},
+            "user": {"id": "10086"},
+            "message": {"id": "m1", "content": ""},
+        });
+        event
+            .as_object_mut()
+            .unwrap()
+            .extend(extra.as_object().unwrap().clone());
+        let event: Event = serde_json::from_value(event).unwrap();
+        let message = match event.try_into_kritor(&ctx).unwrap().event {
+            Some(kritor::event::event_structure::Event::Message(x)) => x,
+            x => panic!("not a message: {:?}", x),
+        };
+        let contact = message.contact.as_ref().unwrap();
+        assert_eq!(contact.scene, i32::from(Scene::Group));
+        assert_eq!(contact.peer, "456");
+        assert!(matches!(
+            &message.sender,
+            Some(Sender::Group(x)) if x.uin == 10086 && x.group_id == "456"
+        ));
+        message
+    }
+
+    #[test]
+    fn test_button_interaction() {
+        let clicked = interaction("interaction/button", json!({"button": {"id": "a"}}));
+        assert!(matches!(
+            &clicked.elements[0].data,
+            Some(KritorElementData::Reply(x)) if x.message_id == "m1"
+        ));
+        assert!(matches!(
+            &clicked.elements[1].data,
+            Some(KritorElementData::Keyboard(x)) if x.rows[0].buttons[0].id == "a"
+        ));
+    }
+
+    #[test]
+    fn test_command_interaction() {
+        let invoked = interaction(
+            "interaction/command",
+            json!({"argv": {"name": "echo", "arguments": ["foo"], "options": {}}}),
+        );
+        assert_eq!(invoked.message_id, "m1");
+        assert!(matches!(
+            &invoked.elements[0].data,
+            Some(KritorElementData::Text(x)) if x.text == "/echo foo"
+        ));
+        assert!(matches!(
+            &invoked.elements[1].data,
+            Some(KritorElementData::Json(x)) if x.json.contains(r#""name":"echo""#)
+        ));
+    }
+
+    #[test]
+    fn test_invalid_content() {
+        let fixture = super::super::message::Fixture::default();
+        let ctx = fixture.context(Default::default());
+        let event: Event = serde_json::from_value(json!({
+            "id": 1,
+            "type": "message-created",
+            "platform": "qq",
+            "self_id": "10000",
+            "timestamp": 1000,
+            "channel": {"id": "456", "type": 0},
+            "user": {"id": "10086"},
+            "message": {"id": "m1", "content": "<b>not closed"},
+        }))
+        .unwrap();
+        // failed rather than delivered empty, so it is kept as a dead letter.
+        assert!(matches!(
+            event.try_into_kritor(&ctx),
+            Err(ConversionError::Content(_))
+        ));
+    }
+
+    #[test]
+    fn test_argv_to_command_line() {
+        let argv: Argv = serde_json::from_value(json!({
+            "name": "echo",
+            "arguments": ["foo", "bar baz", 1],
+            "options": {"loud": true, "times": 2, "quiet": false},
+        }))
+        .unwrap();
+        assert_eq!(
+            argv.to_command_line(),
+            r#"/echo foo "bar baz" 1 --loud --no-quiet --times 2"#
+        );
     }
 }
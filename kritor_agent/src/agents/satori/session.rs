@@ -0,0 +1,108 @@
+use std::collections::{HashSet, VecDeque};
+
+use serde_json::json;
+
+/// How many event ids are remembered to recognize replayed events.
+const SEEN_CAPACITY: usize = 1024;
+
+/// State of the event session kept across reconnects.
+///
+/// The id of the last event from the websocket is sent as `sequence` in
+/// IDENTIFY, so that servers replay the events missed while disconnected.
+/// Events already forwarded, be it by the websocket or webhooks, are told
+/// apart by their ids.
+///
+/// Servers replay only the events after `sequence`, so the first event of a
+/// connection with an id not after it means the server has restarted and
+/// counts from the start again, and the ids seen so far are forgotten.
+#[derive(Default)]
+pub struct Session {
+    last_id: Option<i64>,
+    /// the `sequence` sent in IDENTIFY, until the first event after it.
+    sequence: Option<i64>,
+    seen: HashSet<i64>,
+    order: VecDeque<i64>,
+}
+
+/// Where a signal comes from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    Websocket,
+    Webhook,
+}
+
+impl Session {
+    /// The IDENTIFY signal to send on connecting the websocket.
+    pub fn identify(&mut self, token: Option<&str>) -> String {
+        self.sequence = self.last_id;
+        let mut body = json!({});
+        if let Some(token) = token {
+            body["token"] = json!(token);
+        }
+        if let Some(sequence) = self.last_id {
+            body["sequence"] = json!(sequence);
+        }
+        json!({"op": 3, "body": body}).to_string()
+    }
+    /// Record an event, returning `false` if it has been seen before.
+    pub fn accept(&mut self, id: i64, source: Source) -> bool {
+        if source == Source::Websocket {
+            if let Some(sequence) = self.sequence.take() {
+                if id <= sequence {
+                    log::info!("satori server counts events from {} again, restarted?", id);
+                    self.seen.clear();
+                    self.order.clear();
+                }
+            }
+        }
+        if !self.seen.insert(id) {
+            return false;
+        }
+        if self.order.len() >= SEEN_CAPACITY {
+            if let Some(oldest) = self.order.pop_front() {
+                self.seen.remove(&oldest);
+            }
+        }
+        self.order.push_back(id);
+        if source == Source::Websocket {
+            self.last_id = Some(id);
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resume() {
+        let mut session = Session::default();
+        assert_eq!(
+            session.identify(Some("token")),
+            r#"{"body":{"token":"token"},"op":3}"#
+        );
+        assert!(session.accept(1, Source::Websocket));
+        assert!(session.accept(2, Source::Websocket));
+        // forwarded by a webhook as well.
+        assert!(!session.accept(2, Source::Webhook));
+        assert!(session.accept(4, Source::Webhook));
+        assert_eq!(session.identify(None), r#"{"body":{"sequence":2},"op":3}"#);
+        assert!(session.accept(3, Source::Websocket));
+        assert!(!session.accept(4, Source::Websocket));
+    }
+
+    #[test]
+    fn test_restart() {
+        let mut session = Session::default();
+        for id in 1..=3 {
+            assert!(session.accept(id, Source::Websocket));
+        }
+        session.identify(None);
+        // the server restarted, counting from 1 again.
+        assert!(session.accept(1, Source::Websocket));
+        assert!(session.accept(2, Source::Websocket));
+        assert!(!session.accept(2, Source::Websocket));
+        assert_eq!(session.identify(None), r#"{"body":{"sequence":2},"op":3}"#);
+    }
+}
@@ -0,0 +1,192 @@
+use std::net::TcpListener;
+use std::sync::Arc;
+
+use axum::{extract::State, http::HeaderMap, http::StatusCode, routing::post, Router};
+use serde_json::json;
+use subtle::ConstantTimeEq;
+
+use super::pipeline::Pipeline;
+use super::session::Source;
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct WebhookConfig {
+    /// Address to receive webhooks on, e.g. "0.0.0.0:5141".
+    pub listen: String,
+    /// Path of the webhook endpoint.
+    #[serde(default = "WebhookConfig::default_path")]
+    pub path: String,
+    /// Token satori sends with webhooks, defaulting to the token of the backend.
+    ///
+    /// Webhooks are not received without a token, as anyone who can reach the
+    /// address could push events otherwise.
+    pub token: Option<String>,
+    /// Keep connecting the event websocket besides receiving webhooks.
+    #[serde(default = "WebhookConfig::default_websocket")]
+    pub websocket: bool,
+}
+
+impl WebhookConfig {
+    fn default_path() -> String {
+        "/".into()
+    }
+    fn default_websocket() -> bool {
+        true
+    }
+    /// The token to authorize webhooks with, failing if neither the webhook
+    /// nor the backend has one.
+    pub fn token(&self, backend: Option<&str>) -> std::io::Result<String> {
+        self.token
+            .as_deref()
+            .or(backend)
+            .filter(|x| !x.is_empty())
+            .map(str::to_string)
+            .ok_or_else(|| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    "Refusing to receive webhooks without a token",
+                )
+            })
+    }
+}
+
+struct Webhook {
+    token: String,
+    pipeline: Arc<Pipeline>,
+}
+
+/// Listen on the webhook address, so that the agent fails to start rather than
+/// running without the events it expects from webhooks.
+pub fn bind(config: &WebhookConfig) -> std::io::Result<TcpListener> {
+    let listener = TcpListener::bind(&config.listen).map_err(|e| {
+        std::io::Error::new(
+            e.kind(),
+            format!(
+                "Failed to listen on webhook address {}: {}",
+                config.listen, e
+            ),
+        )
+    })?;
+    listener.set_nonblocking(true)?;
+    Ok(listener)
+}
+
+/// Receive satori webhooks, feeding them into the pipeline until the server fails.
+pub async fn serve(
+    listener: TcpListener,
+    config: WebhookConfig,
+    token: String,
+    pipeline: Arc<Pipeline>,
+) {
+    let app = Router::new()
+        .route(&config.path, post(receive))
+        .with_state(Arc::new(Webhook { token, pipeline }));
+    log::info!(
+        "Receiving satori webhooks on {}{}",
+        config.listen,
+        config.path
+    );
+    let result = match axum::Server::from_tcp(listener) {
+        Ok(server) => server.serve(app.into_make_service()).await,
+        Err(e) => Err(e),
+    };
+    if let Err(e) = result {
+        log::error!("Failed to receive satori webhooks: {}", e);
+    }
+}
+
+async fn receive(
+    State(webhook): State<Arc<Webhook>>,
+    headers: HeaderMap,
+    body: String,
+) -> StatusCode {
+    let authorization = headers
+        .get("Authorization")
+        .and_then(|x| x.to_str().ok())
+        .and_then(|x| x.strip_prefix("Bearer "));
+    // compared in constant time, not to leak the token through timing.
+    let authorized =
+        authorization.is_some_and(|x| bool::from(x.as_bytes().ct_eq(webhook.token.as_bytes())));
+    if !authorized {
+        return StatusCode::UNAUTHORIZED;
+    }
+    let data = match serde_json::from_str::<serde_json::Value>(&body) {
+        Ok(data) => data,
+        Err(e) => {
+            webhook
+                .pipeline
+                .dead_letters
+                .record(body, format!("invalid json: {}", e));
+            return StatusCode::BAD_REQUEST;
+        }
+    };
+    webhook
+        .pipeline
+        .dispatch(signal(&headers, data), Source::Webhook)
+        .await;
+    StatusCode::OK
+}
+
+/// The signal of a webhook, whose body is either a whole signal or, with the
+/// opcode in `Satori-OpCode` or by default EVENT, only its body.
+fn signal(headers: &HeaderMap, data: serde_json::Value) -> serde_json::Value {
+    let op = headers
+        .get("Satori-OpCode")
+        .and_then(|x| x.to_str().ok())
+        .and_then(|x| x.parse::<u8>().ok());
+    match op {
+        Some(op) => json!({"op": op, "body": data}),
+        None if data.get("op").is_some() => data,
+        None => json!({"op": 0, "body": data}),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_signal() {
+        let event = json!({"id": 1, "type": "message-created"});
+        let mut headers = HeaderMap::new();
+        assert_eq!(
+            signal(&headers, event.clone()),
+            json!({"op": 0, "body": event})
+        );
+        let ready = json!({"op": 4, "body": {"logins": []}});
+        assert_eq!(signal(&headers, ready.clone()), ready);
+        headers.insert("Satori-OpCode", "4".parse().unwrap());
+        assert_eq!(
+            signal(&headers, json!({"logins": []})),
+            json!({"op": 4, "body": {"logins": []}})
+        );
+    }
+
+    #[test]
+    fn test_bind() {
+        let mut config = WebhookConfig {
+            listen: "127.0.0.1:0".into(),
+            path: WebhookConfig::default_path(),
+            token: None,
+            websocket: false,
+        };
+        let listener = bind(&config).unwrap();
+        // an address in use fails instead of leaving the agent without events.
+        config.listen = listener.local_addr().unwrap().to_string();
+        assert!(bind(&config).is_err());
+    }
+
+    #[test]
+    fn test_token() {
+        let mut config = WebhookConfig {
+            listen: "127.0.0.1:0".into(),
+            path: WebhookConfig::default_path(),
+            token: None,
+            websocket: false,
+        };
+        assert!(config.token(None).is_err());
+        assert!(config.token(Some("")).is_err());
+        assert_eq!(config.token(Some("backend")).unwrap(), "backend");
+        config.token = Some("webhook".into());
+        assert_eq!(config.token(Some("backend")).unwrap(), "webhook");
+    }
+}
@@ -0,0 +1,210 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures_util::{SinkExt, StreamExt};
+use tokio::select;
+use tokio::time::Instant;
+use tokio_tungstenite::tungstenite;
+
+use super::pipeline::Pipeline;
+use super::session::Source;
+use super::{link, BusEvent, LinkState};
+
+/// Timing of the event websocket, in seconds.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct WebsocketConfig {
+    /// How often PING is sent.
+    #[serde(default = "WebsocketConfig::default_ping_interval")]
+    pub ping_interval: u64,
+    /// How long the connection may stay silent, PONG included, before it is
+    /// considered dead and reconnected.
+    #[serde(default = "WebsocketConfig::default_pong_timeout")]
+    pub pong_timeout: u64,
+    /// The delay before the first retry, doubled on every failed one.
+    #[serde(default = "WebsocketConfig::default_reconnect_min")]
+    pub reconnect_min: u64,
+    /// The longest delay between retries.
+    #[serde(default = "WebsocketConfig::default_reconnect_max")]
+    pub reconnect_max: u64,
+}
+
+impl WebsocketConfig {
+    fn default_ping_interval() -> u64 {
+        10
+    }
+    fn default_pong_timeout() -> u64 {
+        30
+    }
+    fn default_reconnect_min() -> u64 {
+        1
+    }
+    fn default_reconnect_max() -> u64 {
+        60
+    }
+}
+
+impl Default for WebsocketConfig {
+    fn default() -> Self {
+        Self {
+            ping_interval: Self::default_ping_interval(),
+            pong_timeout: Self::default_pong_timeout(),
+            reconnect_min: Self::default_reconnect_min(),
+            reconnect_max: Self::default_reconnect_max(),
+        }
+    }
+}
+
+/// Exponential backoff with jitter between reconnects.
+struct Backoff {
+    min: Duration,
+    max: Duration,
+    attempts: u32,
+}
+
+impl Backoff {
+    fn new(config: &WebsocketConfig) -> Self {
+        let min = Duration::from_secs(config.reconnect_min.max(1));
+        Self {
+            min,
+            max: Duration::from_secs(config.reconnect_max).max(min),
+            attempts: 0,
+        }
+    }
+    /// The delay before the next retry, somewhere between half and all of the
+    /// exponential one so that agents do not retry in lockstep.
+    fn next(&mut self) -> Duration {
+        let delay = self
+            .min
+            .saturating_mul(1 << self.attempts.min(16))
+            .min(self.max);
+        self.attempts += 1;
+        let jitter = RandomState::new().build_hasher().finish() % 1000;
+        delay / 2 + delay / 2 * jitter as u32 / 1000
+    }
+    fn reset(&mut self) {
+        self.attempts = 0;
+    }
+}
+
+/// Whether the signal is READY.
+fn is_ready(data: &serde_json::Value) -> bool {
+    data.get("op").and_then(|x| x.as_u64()) == Some(4)
+}
+
+/// Receive events from the event websocket, reconnecting forever.
+pub async fn run(
+    mut base_url: reqwest::Url,
+    token: Option<String>,
+    config: WebsocketConfig,
+    link: Arc<LinkState>,
+    pipeline: Arc<Pipeline>,
+) {
+    let events = &pipeline.events;
+    let scheme = if base_url.scheme() == "https" {
+        "wss"
+    } else {
+        "ws"
+    };
+    base_url.set_scheme(scheme).unwrap();
+    let ws_endpoint = base_url.join("events").unwrap();
+    let ping_interval = Duration::from_secs(config.ping_interval.max(1));
+    let pong_timeout = Duration::from_secs(config.pong_timeout.max(1));
+    let mut backoff = Backoff::new(&config);
+    loop {
+        log::info!(
+            "try connecting satori event websocket on {}...",
+            ws_endpoint
+        );
+        let conn = tokio_tungstenite::connect_async(&ws_endpoint).await;
+        if let Ok((mut ws, _)) = conn {
+            log::info!("Connected to satori server for event websocket");
+            let connection = link.connect();
+            events.publish(BusEvent {
+                self_id: String::new(),
+                event: link::connected_event(connection),
+            });
+            // resume from the last event, if any.
+            let identify = pipeline.session.lock().unwrap().identify(token.as_deref());
+            let _ = ws.send(tungstenite::Message::text(identify)).await;
+            let mut interval = tokio::time::interval(ping_interval);
+            let mut last_seen = Instant::now();
+            loop {
+                select! {
+                    _ = interval.tick() => {
+                       let r = ws.send(tungstenite::Message::text(r#"{"op":1}"#)).await;
+                       if r.is_err() {
+                           log::error!("Failed to send ping to satori server for event websocket/connection closed?...reconnecting...");
+                           break;
+                       }
+                    },
+                    _ = tokio::time::sleep_until(last_seen + pong_timeout) => {
+                        log::error!("No PONG from satori server for event websocket in {:?}...reconnecting...", pong_timeout);
+                        break;
+                    },
+                    next = ws.next() => {
+                        if let Some(Ok(msg)) = next {
+                            last_seen = Instant::now();
+                            log::debug!("Received message from satori server for event websocket: {:?}", msg);
+                            if let tungstenite::Message::Text(s) = msg{
+                                match serde_json::from_str(s.as_str()) {
+                                    Ok(data) => {
+                                        // only a server that gets as far as READY is up, not
+                                        // one accepting connections and closing them.
+                                        if is_ready(&data) {
+                                            backoff.reset();
+                                        }
+                                        pipeline.dispatch(data, Source::Websocket).await
+                                    }
+                                    Err(e) => pipeline.dead_letters.record(s, format!("invalid json: {}", e)),
+                                }
+                            }
+                        } else {
+                            log::error!("Failed to receive message from satori server for event websocket/connection closed?...reconnecting...");
+                            break;
+                        }
+                    }
+                }
+            }
+            link.disconnect();
+            events.publish(BusEvent {
+                self_id: String::new(),
+                event: link::disconnected_event(connection),
+            });
+        } else {
+            log::error!(
+                "Failed to connect to satori server for event websocket:{}",
+                conn.unwrap_err()
+            );
+        }
+        let delay = backoff.next();
+        log::info!("Reconnecting satori event websocket in {:?}", delay);
+        tokio::time::sleep(delay).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff() {
+        let mut backoff = Backoff::new(&WebsocketConfig {
+            reconnect_min: 2,
+            reconnect_max: 10,
+            ..Default::default()
+        });
+        let bounds = [(1, 2), (2, 4), (4, 8), (5, 10), (5, 10)];
+        for (low, high) in bounds {
+            let delay = backoff.next();
+            assert!(Duration::from_secs(low) <= delay && delay <= Duration::from_secs(high));
+        }
+        backoff.reset();
+        assert!(backoff.next() <= Duration::from_secs(2));
+        assert!(is_ready(
+            &serde_json::json!({"op": 4, "body": {"logins": []}})
+        ));
+        assert!(!is_ready(&serde_json::json!({"op": 0, "body": {}})));
+    }
+}
@@ -14,6 +14,9 @@ use kritor::process::process_service_server::ProcessServiceServer;
 use kritor::reverse::reverse_service_server::ReverseServiceServer;
 use kritor::web::web_service_server::WebServiceServer;
 use kritor_agent::agents::{satori, SatoriAgent};
+use kritor_agent::proto::agent_service_server::AgentServiceServer;
+use kritor_agent::push::{self, PushConfig};
+use kritor_agent::reverse::{self, ReverseConfig};
 use log::info;
 use tonic::transport::Server;
 
@@ -67,8 +70,16 @@ async fn main() -> anyhow::Result<()> {
     };
     let agent = Arc::new(agent);
 
+    if let Some(config) = config.server.reverse.clone() {
+        tokio::spawn(reverse::run(agent.clone(), config));
+    }
+    if let Some(config) = config.server.push.clone() {
+        tokio::spawn(push::run(agent.events.clone(), config));
+    }
+
     let reflection = tonic_reflection::server::Builder::configure()
         .register_encoded_file_descriptor_set(kritor::FILE_DESCRIPTOR_SET)
+        .register_encoded_file_descriptor_set(kritor_agent::proto::FILE_DESCRIPTOR_SET)
         .build()
         .unwrap();
 
@@ -85,6 +96,7 @@ async fn main() -> anyhow::Result<()> {
         .add_service(ProcessServiceServer::from_arc(agent.clone()))
         .add_service(ReverseServiceServer::from_arc(agent.clone()))
         .add_service(WebServiceServer::from_arc(agent.clone()))
+        .add_service(AgentServiceServer::from_arc(agent.clone()))
         .add_service(reflection)
         .serve(config.server.listen.parse()?);
     tokio::select! {
@@ -123,6 +135,10 @@ struct Config {
 struct ServerConfig {
     listen: String,
     rust_log: Option<String>,
+    /// Also connect out to the application and serve requests through `ReverseStream`.
+    reverse: Option<ReverseConfig>,
+    /// Also push events to `EventService.RegisterPassiveListener` of the application.
+    push: Option<PushConfig>,
 }
 
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
@@ -142,6 +158,8 @@ impl Config {
             server: ServerConfig {
                 listen: "127.0.0.1:51405".into(),
                 rust_log: Some("info".into()),
+                reverse: None,
+                push: None,
             },
             backend: BackendConfig::Satori(satori::SatoriConfig {
                 scheme: "http".into(),
@@ -149,7 +167,37 @@ impl Config {
                 port: 15500,
                 path: None,
                 token: Some("super_secret".into()),
+                platform: None,
+                self_id: None,
                 version: "v1".into(),
+                degrade: satori::DegradeConfig {
+                    default: satori::DegradePolicy::Reject,
+                    elements: [
+                        ("music", satori::DegradePolicy::Link),
+                        ("share", satori::DegradePolicy::Link),
+                        ("poke", satori::DegradePolicy::Drop),
+                    ]
+                    .into_iter()
+                    .map(|(k, v)| (k.to_string(), v))
+                    .collect(),
+                },
+                dialect: satori::Dialect::Standard,
+                compute_image_md5: false,
+                image_md5_max_size: 16 * 1024 * 1024,
+                identity_file: Some("identities.tsv".into()),
+                guild_identity_file: Some("guild_identities.tsv".into()),
+                message_index_file: Some("messages.tsv".into()),
+                message_index_capacity: satori::MessageIndex::DEFAULT_CAPACITY,
+                events: satori::EventBusConfig {
+                    capacity: 1024,
+                    on_lag: satori::LagPolicy::Warn,
+                    replay: 1024,
+                    replay_file: None,
+                },
+                dead_letter_capacity: 256,
+                websocket: Default::default(),
+                webhook: None,
+                internal_events: Default::default(),
             }),
         }
     }
@@ -1 +1,11 @@
 pub mod agents;
+pub mod push;
+pub mod reverse;
+
+/// Services of kritor_agent itself, beyond the kritor protocol.
+pub mod proto {
+    pub const FILE_DESCRIPTOR_SET: &[u8] =
+        tonic::include_file_descriptor_set!("agent_descriptor_set");
+
+    tonic::include_proto!("kritor_agent");
+}
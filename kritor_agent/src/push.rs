@@ -0,0 +1,285 @@
+//! Pushing events to the application through `EventService.RegisterPassiveListener`
+//! hosted by it, instead of the application listening to the agent.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use futures_util::StreamExt;
+use kritor::event::event_service_client::EventServiceClient;
+use kritor::event::EventStructure;
+use tokio::sync::Notify;
+
+use crate::agents::satori::{EventBus, EventFilter};
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct PushConfig {
+    /// gRPC address of the application hosting `EventService`, e.g. "http://127.0.0.1:8080".
+    pub address: String,
+    /// Seconds to wait before connecting again.
+    #[serde(default = "PushConfig::default_reconnect_interval")]
+    pub reconnect_interval: u64,
+    /// How many events are kept while the application is unreachable.
+    #[serde(default = "PushConfig::default_buffer")]
+    pub buffer: usize,
+}
+
+impl PushConfig {
+    fn default_reconnect_interval() -> u64 {
+        5
+    }
+    fn default_buffer() -> usize {
+        1024
+    }
+}
+
+/// Events waiting to be pushed, the oldest ones dropped beyond the capacity.
+///
+/// The application does not acknowledge single events, so those taken by a
+/// connection are kept as in flight until it ends. If it fails, they are put
+/// back to be pushed again, at the cost of duplicates the application may
+/// have received already.
+struct Buffer {
+    state: Mutex<State>,
+    capacity: usize,
+    notify: Notify,
+}
+
+#[derive(Default)]
+struct State {
+    queued: VecDeque<EventStructure>,
+    in_flight: VecDeque<EventStructure>,
+    /// the connection events are taken by, so that a failed one takes no more.
+    connection: u64,
+}
+
+impl Buffer {
+    fn new(capacity: usize) -> Self {
+        Self {
+            state: Mutex::new(State::default()),
+            capacity: capacity.max(1),
+            notify: Notify::new(),
+        }
+    }
+    fn push(&self, event: EventStructure) {
+        let mut state = self.state.lock().unwrap();
+        if state.queued.len() + state.in_flight.len() >= self.capacity {
+            // events in flight the longest have most likely been received.
+            if state.in_flight.pop_front().is_none() {
+                state.queued.pop_front();
+                log::warn!("Push buffer is full, dropping the oldest event");
+            }
+        }
+        state.queued.push_back(event);
+        self.notify.notify_one();
+    }
+    /// Start taking events for a new connection.
+    fn connect(&self) -> u64 {
+        self.state.lock().unwrap().connection
+    }
+    /// Take the next event for the connection, or `None` once it has ended.
+    async fn pop(&self, connection: u64) -> Option<EventStructure> {
+        loop {
+            {
+                let mut state = self.state.lock().unwrap();
+                if state.connection != connection {
+                    return None;
+                }
+                if let Some(event) = state.queued.pop_front() {
+                    state.in_flight.push_back(event.clone());
+                    return Some(event);
+                }
+            }
+            self.notify.notified().await;
+        }
+    }
+    /// End the connection, putting the events in flight back if it failed.
+    fn disconnect(&self, failed: bool) {
+        let mut state = self.state.lock().unwrap();
+        state.connection += 1;
+        let in_flight = std::mem::take(&mut state.in_flight);
+        if failed {
+            for event in in_flight.into_iter().rev() {
+                state.queued.push_front(event);
+            }
+        }
+        // wake up the ended connection to let it stop.
+        self.notify.notify_waiters();
+    }
+}
+
+/// Push all the events of the agent to the application, reconnecting forever.
+///
+/// Events are buffered from the start, so that those emitted while the
+/// application is unreachable are pushed once it is back.
+pub async fn run(events: Arc<EventBus>, config: PushConfig) {
+    let buffer = Arc::new(Buffer::new(config.buffer));
+    tokio::spawn(collect(events, buffer.clone()));
+    loop {
+        log::info!("Connecting event push to {}...", config.address);
+        match push(&buffer, &config).await {
+            Ok(()) => log::warn!("Event push to {} ended", config.address),
+            Err(e) => log::error!("Event push to {} failed: {}", config.address, e),
+        }
+        tokio::time::sleep(Duration::from_secs(config.reconnect_interval.max(1))).await;
+    }
+}
+
+/// Move the events of every type into the buffer, in the order they are published.
+async fn collect(events: Arc<EventBus>, buffer: Arc<Buffer>) {
+    loop {
+        let mut subscription = events.subscribe(EventFilter::default());
+        while let Some(event) = subscription.next().await {
+            match event {
+                Ok(event) => buffer.push(event),
+                Err(e) => log::warn!("Missed events for pushing: {}", e),
+            }
+        }
+        // the subscription ended, e.g. when disconnected for lagging behind.
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+}
+
+async fn push(buffer: &Arc<Buffer>, config: &PushConfig) -> anyhow::Result<()> {
+    let mut client = EventServiceClient::connect(config.address.clone()).await?;
+    log::info!("Pushing events to {}", config.address);
+    let connection = buffer.connect();
+    let events = futures_util::stream::unfold(buffer.clone(), move |buffer| async move {
+        let event = buffer.pop(connection).await?;
+        Some((event, buffer))
+    });
+    let result = client.register_passive_listener(events).await;
+    buffer.disconnect(result.is_err());
+    result?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agents::satori::event_time as time;
+    use kritor::event::event_service_server::{EventService, EventServiceServer};
+    use kritor::event::{event_structure::Event, EventType, NoticeEvent, RequestPushEvent};
+    use tokio_stream::wrappers::TcpListenerStream;
+
+    fn notice(time: u64) -> EventStructure {
+        EventStructure {
+            r#type: EventType::Notice.into(),
+            event: Some(Event::Notice(NoticeEvent {
+                time,
+                ..Default::default()
+            })),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_buffer() {
+        let buffer = Buffer::new(2);
+        for time in 1..=3 {
+            buffer.push(notice(time));
+        }
+        let connection = buffer.connect();
+        assert_eq!(buffer.pop(connection).await.map(|x| time(&x)), Some(2));
+        buffer.disconnect(true);
+        assert!(buffer.pop(connection).await.is_none());
+        let connection = buffer.connect();
+        assert_eq!(buffer.pop(connection).await.map(|x| time(&x)), Some(2));
+        assert_eq!(buffer.pop(connection).await.map(|x| time(&x)), Some(3));
+        buffer.disconnect(false);
+        assert!(buffer.state.lock().unwrap().queued.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_collect_order() {
+        let events = Arc::new(EventBus::new(&Default::default()).unwrap());
+        let buffer = Arc::new(Buffer::new(16));
+        tokio::spawn(collect(events.clone(), buffer.clone()));
+        // let it subscribe.
+        tokio::task::yield_now().await;
+        for time in 1..=4 {
+            let event = if time % 2 == 0 {
+                notice(time)
+            } else {
+                EventStructure {
+                    r#type: EventType::Message.into(),
+                    event: Some(Event::Message(kritor::common::PushMessageBody {
+                        time,
+                        ..Default::default()
+                    })),
+                }
+            };
+            events.publish(crate::agents::satori::BusEvent {
+                self_id: String::new(),
+                event,
+            });
+        }
+        // events of different types are pushed in the order they are published.
+        let connection = buffer.connect();
+        for expected in 1..=4 {
+            assert_eq!(
+                buffer.pop(connection).await.map(|x| time(&x)),
+                Some(expected)
+            );
+        }
+    }
+
+    /// Breaks the first connection after two events, and takes the others
+    /// until the event of time `last`.
+    struct Receiver {
+        connections: Mutex<Vec<Vec<u64>>>,
+        last: u64,
+    }
+
+    #[tonic::async_trait]
+    impl EventService for Receiver {
+        async fn register_passive_listener(
+            &self,
+            request: tonic::Request<tonic::Streaming<EventStructure>>,
+        ) -> Result<tonic::Response<RequestPushEvent>, tonic::Status> {
+            let first = self.connections.lock().unwrap().is_empty();
+            let mut events = request.into_inner();
+            let mut received = Vec::new();
+            while let Some(event) = events.message().await? {
+                received.push(time(&event));
+                if (first && received.len() == 2) || time(&event) == self.last {
+                    break;
+                }
+            }
+            self.connections.lock().unwrap().push(received);
+            if first {
+                return Err(tonic::Status::unavailable("connection broken"));
+            }
+            Ok(tonic::Response::new(RequestPushEvent::default()))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reconnect() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = format!("http://{}", listener.local_addr().unwrap());
+        let receiver = Arc::new(Receiver {
+            connections: Mutex::new(Vec::new()),
+            last: 3,
+        });
+        tokio::spawn(
+            tonic::transport::Server::builder()
+                .add_service(EventServiceServer::from_arc(receiver.clone()))
+                .serve_with_incoming(TcpListenerStream::new(listener)),
+        );
+        let buffer = Arc::new(Buffer::new(16));
+        for time in 1..=3 {
+            buffer.push(notice(time));
+        }
+        let config = PushConfig {
+            address,
+            reconnect_interval: 1,
+            buffer: 16,
+        };
+        // the connection breaks after the first events, which are pushed again.
+        assert!(push(&buffer, &config).await.is_err());
+        assert!(push(&buffer, &config).await.is_ok());
+        let connections = receiver.connections.lock().unwrap().clone();
+        assert_eq!(connections, vec![vec![1, 2], vec![1, 2, 3]]);
+        assert!(buffer.state.lock().unwrap().queued.is_empty());
+    }
+}
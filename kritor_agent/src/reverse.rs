@@ -0,0 +1,258 @@
+//! The reverse connection mode, where the agent connects out to the application
+//! and serves the requests coming through `ReverseService.ReverseStream`.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use kritor::common::{
+    response::ResponseCode, Request as ReverseRequest, Response as ReverseResponse,
+};
+use kritor::core::core_service_server::CoreService;
+use kritor::developer::developer_service_server::DeveloperService;
+use kritor::friend::friend_service_server::FriendService;
+use kritor::guild::guild_service_server::GuildService;
+use kritor::message::message_service_server::MessageService;
+use kritor::reverse::reverse_service_client::ReverseServiceClient;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::metadata::MetadataMap;
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct ReverseConfig {
+    /// gRPC address of the application, e.g. "http://127.0.0.1:8080".
+    pub address: String,
+    /// Seconds to wait before connecting again.
+    #[serde(default = "ReverseConfig::default_reconnect_interval")]
+    pub reconnect_interval: u64,
+}
+
+impl ReverseConfig {
+    fn default_reconnect_interval() -> u64 {
+        5
+    }
+}
+
+/// The services that can be called through the reverse stream.
+pub trait Services:
+    CoreService + DeveloperService + FriendService + GuildService + MessageService
+{
+}
+impl<T> Services for T where
+    T: CoreService + DeveloperService + FriendService + GuildService + MessageService
+{
+}
+
+/// Keep a reverse stream to the application open, reconnecting forever.
+pub async fn run<A: Services>(agent: Arc<A>, config: ReverseConfig) {
+    loop {
+        log::info!("Connecting reverse stream to {}...", config.address);
+        match serve(&agent, &config).await {
+            Ok(()) => log::warn!("Reverse stream to {} ended", config.address),
+            Err(e) => log::error!("Reverse stream to {} failed: {}", config.address, e),
+        }
+        tokio::time::sleep(Duration::from_secs(config.reconnect_interval.max(1))).await;
+    }
+}
+
+async fn serve<A: Services>(agent: &Arc<A>, config: &ReverseConfig) -> anyhow::Result<()> {
+    let mut client = ReverseServiceClient::connect(config.address.clone()).await?;
+    let (tx, rx) = mpsc::channel(64);
+    let stream = client.reverse_stream(ReceiverStream::new(rx)).await?;
+    // the requests carry no metadata of their own, so the initial headers of
+    // the stream are used for all of them.
+    let metadata = stream.metadata().clone();
+    let mut requests = stream.into_inner();
+    log::info!("Reverse stream to {} opened", config.address);
+    while let Some(request) = requests.message().await? {
+        let agent = agent.clone();
+        let tx = tx.clone();
+        let metadata = metadata.clone();
+        tokio::spawn(async move {
+            let no_response = request.no_response;
+            let response = handle(&*agent, request, metadata).await;
+            if !no_response {
+                let _ = tx.send(response).await;
+            }
+        });
+    }
+    Ok(())
+}
+
+async fn handle<A: Services>(
+    agent: &A,
+    request: ReverseRequest,
+    metadata: MetadataMap,
+) -> ReverseResponse {
+    let result = match parse_cmd(&request.cmd) {
+        Some((service, method)) => call(agent, service, method, &request.buf, metadata).await,
+        None => Err(tonic::Status::not_found("unsupported cmd")),
+    };
+    match result {
+        Ok(buf) => ReverseResponse {
+            cmd: request.cmd,
+            seq: request.seq,
+            code: ResponseCode::Success.into(),
+            msg: String::new(),
+            buf,
+        },
+        Err(status) => {
+            log::warn!("Reverse request {} failed: {}", request.cmd, status);
+            ReverseResponse {
+                cmd: request.cmd,
+                seq: request.seq,
+                code: response_code(status.code()).into(),
+                msg: status.message().to_string(),
+                buf: Vec::new(),
+            }
+        }
+    }
+}
+
+/// The service and method of a cmd, which is either a gRPC path such as
+/// `/kritor.core.CoreService/GetVersion`, or in the form of `CoreService.GetVersion`.
+fn parse_cmd(cmd: &str) -> Option<(&str, &str)> {
+    let cmd = cmd.trim_start_matches('/');
+    let (service, method) = cmd.rsplit_once('/').or_else(|| cmd.rsplit_once('.'))?;
+    Some((service.rsplit('.').next()?, method))
+}
+
+/// Services of kritor served by the agent, whose methods not listed in `call`
+/// are unimplemented rather than unknown.
+const KNOWN_SERVICES: &[&str] = &[
+    "AuthenticationService",
+    "CoreService",
+    "DeveloperService",
+    "EventService",
+    "GroupFileService",
+    "FriendService",
+    "GroupService",
+    "GuildService",
+    "MessageService",
+    "ProcessService",
+    "ReverseService",
+    "WebService",
+];
+
+macro_rules! dispatch {
+    ($agent:expr, $service:expr, $method:expr, $buf:expr, $metadata:expr, {
+        $($trait:ident . $name:ident => $fn:ident,)*
+    }) => {
+        match ($service, $method) {
+            $((stringify!($trait), stringify!($name)) => {
+                let request = prost::Message::decode($buf).map_err(|e| {
+                    tonic::Status::invalid_argument(format!("Failed to decode the request: {}", e))
+                })?;
+                let request = tonic::Request::from_parts($metadata, Default::default(), request);
+                let response = $trait::$fn($agent, request).await?;
+                Ok(prost::Message::encode_to_vec(response.get_ref()))
+            })*
+            (service, _) if KNOWN_SERVICES.contains(&service) => {
+                Err(tonic::Status::unimplemented("unimplemented cmd"))
+            }
+            _ => Err(tonic::Status::not_found("unsupported cmd")),
+        }
+    };
+}
+
+async fn call<A: Services>(
+    agent: &A,
+    service: &str,
+    method: &str,
+    buf: &[u8],
+    metadata: MetadataMap,
+) -> Result<Vec<u8>, tonic::Status> {
+    dispatch!(agent, service, method, buf, metadata, {
+        CoreService.GetVersion => get_version,
+        CoreService.GetCurrentAccount => get_current_account,
+        CoreService.SwitchAccount => switch_account,
+        DeveloperService.GetLog => get_log,
+        FriendService.GetUidByUin => get_uid_by_uin,
+        FriendService.GetUinByUid => get_uin_by_uid,
+        GuildService.GetBotInfo => get_bot_info,
+        GuildService.GetChannelList => get_channel_list,
+        GuildService.GetGuildMetaByGuest => get_guild_meta_by_guest,
+        GuildService.GetGuildChannelList => get_guild_channel_list,
+        GuildService.GetGuildMemberList => get_guild_member_list,
+        GuildService.GetGuildMember => get_guild_member,
+        GuildService.GetGuildRoleList => get_guild_role_list,
+        MessageService.SendMessage => send_message,
+        MessageService.SendMessageByResId => send_message_by_res_id,
+        MessageService.GetMessageBySeq => get_message_by_seq,
+        MessageService.GetHistoryMessageBySeq => get_history_message_by_seq,
+        MessageService.UploadForwardMessage => upload_forward_message,
+        MessageService.DownloadForwardMessage => download_forward_message,
+    })
+}
+
+fn response_code(code: tonic::Code) -> ResponseCode {
+    match code {
+        tonic::Code::NotFound => ResponseCode::NotFound,
+        tonic::Code::InvalidArgument => ResponseCode::InvalidArgument,
+        tonic::Code::Unauthenticated => ResponseCode::Unauthenticated,
+        tonic::Code::PermissionDenied => ResponseCode::PermissionDenied,
+        _ => ResponseCode::Internal,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kritor::core::{GetVersionRequest, GetVersionResponse};
+
+    #[test]
+    fn test_parse_cmd() {
+        assert_eq!(
+            parse_cmd("/kritor.core.CoreService/GetVersion"),
+            Some(("CoreService", "GetVersion"))
+        );
+        assert_eq!(
+            parse_cmd("CoreService.GetVersion"),
+            Some(("CoreService", "GetVersion"))
+        );
+        assert_eq!(parse_cmd("GetVersion"), None);
+    }
+
+    struct Stub;
+    #[tonic::async_trait]
+    impl CoreService for Stub {
+        async fn get_version(
+            &self,
+            request: tonic::Request<GetVersionRequest>,
+        ) -> Result<tonic::Response<GetVersionResponse>, tonic::Status> {
+            let self_id = request.metadata().get("x-self-id");
+            Ok(tonic::Response::new(GetVersionResponse {
+                version: self_id
+                    .and_then(|x| x.to_str().ok())
+                    .unwrap_or_default()
+                    .into(),
+                app_name: String::new(),
+            }))
+        }
+    }
+    impl DeveloperService for Stub {}
+    impl FriendService for Stub {}
+    impl GuildService for Stub {}
+    impl MessageService for Stub {}
+
+    #[tokio::test]
+    async fn test_handle() {
+        let mut metadata = MetadataMap::new();
+        metadata.insert("x-self-id", "10000".parse().unwrap());
+        let request = |cmd: &str| ReverseRequest {
+            cmd: cmd.into(),
+            seq: 1,
+            buf: Vec::new(),
+            no_response: false,
+        };
+        let response = handle(&Stub, request("CoreService.GetVersion"), metadata.clone()).await;
+        assert_eq!(response.code(), ResponseCode::Success);
+        let version: GetVersionResponse = prost::Message::decode(&response.buf[..]).unwrap();
+        assert_eq!(version.version, "10000");
+
+        let response = handle(&Stub, request("CoreService.DownloadFile"), metadata.clone()).await;
+        assert_eq!(response.code(), ResponseCode::Internal);
+        assert_eq!(response.msg, "unimplemented cmd");
+        let response = handle(&Stub, request("FooService.Bar"), metadata).await;
+        assert_eq!(response.code(), ResponseCode::NotFound);
+    }
+}